@@ -1,13 +1,23 @@
 use sass_rs::{compile_file, Options, OutputStyle};
+use sha2::{Digest, Sha256};
 use std::{env::var, fs, io::Result, path::PathBuf, process::Command};
 use which::which;
 
+/// Get the content security policy source that allows an inline script or style
+fn csp_hash(content: &str) -> String {
+    format!(
+        "'sha256-{}'",
+        base64::encode(Sha256::digest(content.as_bytes()))
+    )
+}
+
 fn main() -> Result<()> {
     let debug = var("PROFILE").unwrap_or_default() == "debug";
 
     println!("cargo:rerun-if-changed=build.rs");
 
     // COMPILE TS
+    let mut script_hashes = Vec::new();
     let scripts_listing = fs::read_dir("src/scripts")?.collect::<Result<Vec<_>>>()?;
 
     println!("cargo:rerun-if-changed=src/scripts");
@@ -44,6 +54,12 @@ fn main() -> Result<()> {
             .expect("`tsc` failed to run");
 
         if output.status.success() {
+            let content = format!(
+                "\n{}\n",
+                String::from_utf8_lossy(&fs::read("target/scripts/temp.js")?)
+            );
+            script_hashes.push(csp_hash(&content));
+
             fs::write(
                 format!(
                     "target/scripts/{}",
@@ -53,10 +69,7 @@ fn main() -> Result<()> {
                         .to_string_lossy()
                         .replace(".ts", ".js.html")
                 ),
-                format!(
-                    "<script>\n{}\n</script>",
-                    String::from_utf8_lossy(&fs::read("target/scripts/temp.js")?)
-                ),
+                format!("<script>{}</script>", content),
             )?;
 
             fs::remove_file("target/scripts/temp.js")?;
@@ -71,6 +84,7 @@ fn main() -> Result<()> {
     }
 
     // COMPILE SCSS
+    let mut style_hashes = Vec::new();
 
     let styles_listing = fs::read_dir("src/styles")?.collect::<Result<Vec<_>>>()?;
     println!("cargo:rerun-if-changed=src/styles");
@@ -99,6 +113,8 @@ fn main() -> Result<()> {
             eprintln!("{}", e);
             panic!("{}", e);
         });
+        let content = format!("\n{}\n", content);
+        style_hashes.push(csp_hash(&content));
 
         fs::write(
             format!(
@@ -110,9 +126,21 @@ fn main() -> Result<()> {
                     .to_string_lossy()
                     .replace(".scss", ".css.html")
             ),
-            format!("<style>\n{}\n</style>", content),
+            format!("<style>{}</style>", content),
         )?;
     }
 
+    // HASH INLINE SCRIPTS AND STYLES
+    fs::write(
+        PathBuf::from(var("OUT_DIR").unwrap()).join("inline_hashes.rs"),
+        format!(
+            "/// The sources that allow the inline scripts of the web UI\n\
+             pub const SCRIPT_SOURCES: &[&str] = &{:?};\n\
+             /// The sources that allow the inline styles of the web UI\n\
+             pub const STYLE_SOURCES: &[&str] = &{:?};\n",
+            script_hashes, style_hashes
+        ),
+    )?;
+
     Ok(())
 }
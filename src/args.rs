@@ -24,10 +24,33 @@ pub struct Args {
     /// The path to the config file
     #[structopt(short, long, default_value = "data/config.toml")]
     pub config: PathBuf,
-    /// The path to output the generated ShareX custom uploaders file
+    /// The path to output the generated ShareX custom uploaders files for each user
     #[structopt(short, long, default_value = "data/uploaders")]
     pub uploaders: PathBuf,
     /// The path to the sqlite database that holds the mappings between uploads and their files aswell as
     #[structopt(short = "db", long, default_value = "data/db.sqlite")]
     pub database: PathBuf,
+    /// The path to the directory that the contents of uploads are stored in
+    #[structopt(short, long, default_value = "data/uploads")]
+    pub storage: PathBuf,
+
+    /// A task to run instead of starting the server
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Tasks that can be run instead of starting the server
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Apply any pending migrations to the database
+    Migrate {
+        /// Only print the pending migrations without applying them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Hash a password to be stored in the config file
+    HashPassword {
+        /// The password to hash (read from stdin if not given)
+        password: Option<String>,
+    },
 }
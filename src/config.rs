@@ -1,25 +1,104 @@
 //! The app wide configuration and tools to assist with manipulating it
 
-use crate::generate::generate_base64;
-use crate::{create_parent_directories, templates::ConfigTemplate, user::User};
+use crate::generate::{generate_base64, generate_secret_key};
+use crate::{
+    create_parent_directories,
+    database::Visibility,
+    templates::ConfigTemplate,
+    user::{constant_time_eq, hash_password, Password, User},
+};
 use askama::Template;
+use colored::*;
 use io::ErrorKind;
+use rocket::Request;
 use serde::{Deserialize, Deserializer};
 use std::fs;
 use std::{collections::HashMap, io, path::Path, sync::Arc};
 
+/// A user as it is written in the config file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UserEntry {
+    /// A user with only a password, as written by older versions
+    Password(String),
+    /// A user with a password, their upload tokens and storage quota
+    Full {
+        password: String,
+        #[serde(default)]
+        tokens: Vec<String>,
+        #[serde(default)]
+        quota: Option<u64>,
+    },
+}
+
 fn deserialize_users<'de, D>(deserializer: D) -> Result<Arc<[User]>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let map: HashMap<String, String> = Deserialize::deserialize(deserializer)?;
+    let map: HashMap<String, UserEntry> = Deserialize::deserialize(deserializer)?;
 
     Ok(map
         .into_iter()
-        .map(|(username, password)| User { username, password })
+        .map(|(username, entry)| match entry {
+            UserEntry::Password(password) => User {
+                username,
+                password: Password::from_config(password),
+                tokens: Vec::new(),
+                quota: None,
+            },
+            UserEntry::Full {
+                password,
+                tokens,
+                quota,
+            } => User {
+                username,
+                password: Password::from_config(password),
+                tokens,
+                quota,
+            },
+        })
         .collect::<Arc<_>>())
 }
 
+/// The amount of days that accesses are logged for when it is not set in the config
+fn default_access_log_days() -> u32 {
+    30
+}
+
+/// How uploads with active content, which could run scripts on the site if they were shown, are served
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActiveContent {
+    /// Have the browser download them instead of showing them
+    Attachment,
+    /// Show them with a content security policy that stops them from running scripts
+    Sandbox,
+    /// Redirect to the `user_content_domain`, where they can not reach the site
+    Domain,
+}
+
+impl Default for ActiveContent {
+    fn default() -> Self {
+        ActiveContent::Sandbox
+    }
+}
+
+/// Which url to an upload is given to ShareX after uploading
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareUrl {
+    /// The url of the file itself
+    Direct,
+    /// The url of the preview page, which chat apps and social media can embed
+    Preview,
+}
+
+impl Default for ShareUrl {
+    fn default() -> Self {
+        ShareUrl::Direct
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 /// The configuration for the app
 pub struct Config {
@@ -37,11 +116,33 @@ pub struct Config {
     /// with cloudflare to use a different domain for direct comms
     /// to the server, bypassing cloudflare**
     pub upload_domain: Option<String>,
-    /// The token to use for uploading files from sharex
-    /// (Treat this like a password, it is an all access pass to upload)
-    pub upload_token: String,
+    /// The base64 encoded 256-bit key used to encrypt session cookies. Without it, a new key is
+    /// generated on every start and everyone will have to log in again
+    pub secret_key: Option<String>,
+    /// The key used to sign time-limited urls to uploads. Without it, signed urls can not be made
+    pub signing_key: Option<String>,
+    /// **Deprecated:** A token shared between all users for uploading files from sharex.
+    /// Uploads made with it are not attributed to any user, so give each user their own tokens instead
+    pub upload_token: Option<String>,
+    /// How many days to keep the log of accesses to uploads and links for, where 0 disables the log
+    #[serde(default = "default_access_log_days")]
+    pub access_log_days: u32,
+    /// The largest upload in bytes that is accepted, if uploads are limited in size
+    pub max_upload_size: Option<u64>,
+    /// Who can view new uploads that are not given a visibility when they are uploaded
+    #[serde(default)]
+    pub default_visibility: Visibility,
+    /// How uploads that could run scripts, such as html and svg files, are served
+    #[serde(default)]
+    pub active_content: ActiveContent,
+    /// The domain that uploads with active content are served from when `active_content` is `domain`.
+    /// It must point to this server, but should not share cookies with the `domain`
+    pub user_content_domain: Option<String>,
+    /// Which url to an upload the uploader template gives to ShareX
+    #[serde(default)]
+    pub share_url: ShareUrl,
     #[serde(deserialize_with = "deserialize_users")]
-    /// The users to have access to the files
+    /// The users to have access to the files, along with the tokens they upload with
     pub users: Arc<[User]>,
 }
 
@@ -58,21 +159,119 @@ impl Config {
             create_parent_directories(&path)?;
 
             debug!("Creating config file {:?} from template", path);
+            let admin_password = generate_base64(25);
+
             // Write template if file does not exist
             fs::write(
                 path,
                 ConfigTemplate {
+                    secret_key: &generate_secret_key(),
+                    signing_key: &generate_secret_key(),
                     upload_token: &generate_base64(100),
-                    admin_password: &generate_base64(25),
+                    admin_password_hash: &hash_password(&admin_password)
+                        .map_err(|e| io::Error::new(ErrorKind::Other, e))?,
                 }
                 .render()
                 .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
             )?;
+
+            // Only the hash is stored, so this is the only time the password can be seen
+            println!(
+                "{} {}",
+                "Created the default admin account with the password:".green(),
+                admin_password
+            );
         }
 
         trace!("Reading file contents from config file {:?}", path);
         // Parse in the toml config file
-        Ok(toml::from_str::<Config>(&fs::read_to_string(path)?)?)
+        let mut config = toml::from_str::<Config>(&fs::read_to_string(path)?)?;
+
+        if config.secret_key.is_none() {
+            warn!(
+                "No `secret_key` is set in {:?}, so everyone will be logged out whenever the server restarts",
+                path
+            );
+        }
+
+        if config.upload_token.is_some() {
+            warn!(
+                "The shared `upload_token` in {:?} is deprecated, uploads made with it will not be attributed to any user. Give each user their own `tokens` instead",
+                path
+            );
+        }
+
+        if config.active_content == ActiveContent::Domain {
+            match &config.user_content_domain {
+                Some(domain) if !domain.eq_ignore_ascii_case(&config.domain) => {}
+                _ => {
+                    warn!(
+                        "`active_content` in {:?} is `domain`, but there is no separate `user_content_domain`. Sandboxing active content instead",
+                        path
+                    );
+                    config.active_content = ActiveContent::Sandbox;
+                }
+            }
+        }
+
+        // Warn about any users that still have their password stored in plain text
+        for user in config.users.iter() {
+            if let Password::Plaintext(_) = user.password {
+                warn!(
+                    "{} {} {}",
+                    "The password for user".red(),
+                    user.username,
+                    "is stored in plain text, which is deprecated. Replace it with the output of `sxfs hash-password`".red()
+                );
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Check if a request was made to the `user_content_domain`, where uploads with active content are served.
+    /// Nothing but uploads should be trusted there
+    pub fn is_user_content_host(&self, request: &Request) -> bool {
+        if self.active_content != ActiveContent::Domain {
+            return false;
+        }
+
+        match (&self.user_content_domain, request.headers().get_one("Host")) {
+            (Some(domain), Some(host)) => host.eq_ignore_ascii_case(domain),
+            _ => false,
+        }
+    }
+
+    /// Find the user with the given username and password
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<&User> {
+        match self.users.iter().find(|u| u.username == username) {
+            Some(user) if user.password.verify(password) => Some(user),
+            Some(_) => None,
+            None => {
+                // Hash the password anyways, so that the response takes as long as if the user existed
+                hash_password(password).ok();
+
+                None
+            }
+        }
+    }
+
+    /// Find the user that an upload token belongs to, checking every user's tokens
+    pub fn token_user(&self, token: &str) -> Option<&User> {
+        self.users.iter().fold(None, |found, user| {
+            if user.has_token(token) {
+                Some(user)
+            } else {
+                found
+            }
+        })
+    }
+
+    /// Check if the given token is the deprecated shared upload token
+    pub fn is_shared_token(&self, token: &str) -> bool {
+        self.upload_token
+            .as_ref()
+            .map_or(false, |t| constant_time_eq(t, token))
     }
 }
 
@@ -0,0 +1,35 @@
+//! The content security policy of the web UI, which only lets its pages run the scripts and
+//! styles that were built into them
+
+use crate::config::{ActiveContent, Config};
+
+/// The hashes of the inline scripts and styles, written by the build script
+mod inline {
+    include!(concat!(env!("OUT_DIR"), "/inline_hashes.rs"));
+}
+
+/// Where the images on the error pages are loaded from
+const ERROR_IMAGES: &str = "https://i.ytimg.com";
+
+/// Build the content security policy for the pages of the web UI
+#[must_use]
+pub fn ui_policy(config: &Config) -> String {
+    // Uploads can be sent to the upload domain, and shown from the user content domain
+    let upload_domain = config.upload_domain.as_deref().unwrap_or_default();
+    let user_content_domain = match config.active_content {
+        ActiveContent::Domain => config.user_content_domain.as_deref().unwrap_or_default(),
+        ActiveContent::Attachment | ActiveContent::Sandbox => "",
+    };
+
+    format!(
+        "default-src 'none'; script-src {}; style-src 'self' {}; img-src 'self' data: {} {}; \
+         media-src 'self' {}; frame-src 'self'; connect-src 'self' {}; frame-ancestors 'none'; \
+         base-uri 'none'",
+        inline::SCRIPT_SOURCES.join(" "),
+        inline::STYLE_SOURCES.join(" "),
+        ERROR_IMAGES,
+        user_content_domain,
+        user_content_domain,
+        upload_domain
+    )
+}
@@ -1,11 +1,27 @@
 //! Wrapper for the sql database as to provide storage
-use crate::id::ID;
-use chrono::NaiveDateTime;
+use crate::{
+    id::ID,
+    sniff,
+    storage::{BlobKey, StoredBlob},
+};
+use chrono::{NaiveDate, NaiveDateTime};
 use derive_more::Deref;
+use page::{
+    ContentKind, LinkFilter, LinkSort, Order, Page, PageQuery, Position, UploadFilter, UploadSort,
+};
 use rocket::http::ContentType;
 use rocket_contrib::database;
-use rusqlite::{types::FromSqlError, Connection};
-use std::{convert::TryInto, path::Path};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef},
+    Connection, Row,
+};
+use search::SEARCH_LIMIT;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub mod migrations;
+pub mod page;
+pub mod search;
 
 /// Wrapper for the sql database as to provide storage
 #[database("db")]
@@ -22,6 +38,96 @@ impl Database {
     pub fn links(&self) -> LinkTable {
         LinkTable(&self)
     }
+
+    /// Get the sessions table and methods to deal with it
+    pub fn sessions(&self) -> SessionTable {
+        SessionTable(&self)
+    }
+
+    /// Get the access log table and methods to deal with it
+    pub fn accesses(&self) -> AccessTable {
+        AccessTable(&self)
+    }
+}
+
+/// The outcome of accessing an upload or link that may only be accessed a limited amount of times
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// The access was allowed
+    Allowed,
+    /// The access was allowed but was the last one, so the upload or link should be deleted
+    Last,
+    /// There are no accesses left
+    Exhausted,
+}
+
+/// Who can view an upload
+#[derive(Debug, Clone, Copy, PartialEq, FromFormValue, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone with the link can view the upload
+    Public,
+    /// Anyone with the link can view the upload, but search engines are asked not to index it
+    Unlisted,
+    /// Only logged in users can view the upload
+    Private,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Public
+    }
+}
+
+impl Visibility {
+    /// The name of the visibility as it is stored in the database and used in query strings
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+impl ToSql for Visibility {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for Visibility {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            "private" => Ok(Visibility::Private),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Run a function inside of a transaction that holds the write lock for the database from the start,
+/// so that no other connection can change what it reads before it is committed
+fn immediate<T, F: FnOnce() -> rusqlite::Result<T>>(
+    connection: &Connection,
+    f: F,
+) -> rusqlite::Result<T> {
+    connection.execute_batch("BEGIN IMMEDIATE")?;
+
+    match f() {
+        Ok(value) => {
+            connection.execute_batch("COMMIT")?;
+
+            Ok(value)
+        }
+        Err(e) => {
+            connection.execute_batch("ROLLBACK")?;
+
+            Err(e)
+        }
+    }
 }
 
 /// Connection to an upload table
@@ -38,138 +144,394 @@ pub struct UploadMetadata {
     pub size: u64,
     /// The timestamp of when the upload was created
     pub timestamp: NaiveDateTime,
+    /// The key of the upload's contents in the storage backend
+    pub blob: BlobKey,
+    /// The SHA-256 hash of the upload's contents, as lowercase hex
+    pub sha256: String,
+    /// The username of the user that created the upload
+    pub owner: Option<String>,
+    /// When the upload expires and should no longer be accessible
+    pub expires_at: Option<NaiveDateTime>,
+    /// The amount of times the upload can still be viewed before it is deleted
+    pub max_views: Option<u32>,
+    /// The amount of times the upload has been viewed
+    pub views: u64,
+    /// The argon2 hash of the password needed to view the upload, if it is protected
+    pub password: Option<String>,
+    /// Who can view the upload
+    pub visibility: Visibility,
+    /// The MIME type of the upload, detected from its contents when it was uploaded
+    pub content_type: String,
 }
 
 impl UploadMetadata {
+    /// Helper fn to check if an upload has expired
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
+
     /// Helper fn to check if an upload is an image
     pub fn is_image(&self) -> bool {
-        if let Some(ext) = Path::new(&self.filename).extension() {
-            if let Some(ct) = ContentType::from_extension(&ext.to_string_lossy()) {
-                ct.top() == "image"
-            } else {
-                false
-            }
+        self.media_type().top() == "image"
+    }
+
+    /// Helper fn to get the content type that the upload is served with
+    pub fn media_type(&self) -> ContentType {
+        ContentType::parse_flexible(&self.content_type).unwrap_or(ContentType::Binary)
+    }
+
+    /// Helper fn to get the kind of content that the upload can be played or shown as, if any
+    pub fn media_kind(&self) -> Option<ContentKind> {
+        let media_type = self.media_type();
+        let top = media_type.top();
+
+        if top == "image" {
+            Some(ContentKind::Image)
+        } else if top == "video" {
+            Some(ContentKind::Video)
+        } else if top == "audio" {
+            Some(ContentKind::Audio)
+        } else if sniff::is_textual(&media_type) || media_type.sub() == "pdf" {
+            Some(ContentKind::Text)
         } else {
-            false
+            None
         }
     }
 }
 
-/// The data that is stored in an upload
-pub type UploadData = [u8];
+/// Helper fn to read an upload from a row with the columns `id, filename, size, timestamp, blob, owner,
+/// expires_at, max_views, views, password, visibility, sha256, content_type` in that order
+fn upload_from_row(row: &Row) -> rusqlite::Result<UploadMetadata> {
+    Ok(UploadMetadata {
+        id: row.get_checked(0)?,
+        filename: row.get_checked(1)?,
+        size: row.get_checked::<_, i64>(2)? as u64,
+        timestamp: NaiveDateTime::from_timestamp(row.get_checked(3)?, 0),
+        blob: row.get_checked(4)?,
+        owner: row.get_checked(5)?,
+        expires_at: row
+            .get_checked::<_, Option<i64>>(6)?
+            .map(|expires_at| NaiveDateTime::from_timestamp(expires_at, 0)),
+        max_views: row.get_checked(7)?,
+        views: row.get_checked::<_, i64>(8)? as u64,
+        password: row.get_checked(9)?,
+        visibility: row.get_checked(10)?,
+        sha256: row.get_checked(11)?,
+        content_type: row.get_checked(12)?,
+    })
+}
 
 impl<'a> UploadTable<'a> {
-    /// Method to create the table if it does not exist
-    fn ensure_table_exists(&self) -> rusqlite::Result<()> {
-        self.execute(
-            "CREATE TABLE IF NOT EXISTS uploads (
-                id            BLOB PRIMARY KEY NOT NULL,
-                filename      TEXT NOT NULL,
-                size          BLOB NOT NULL,
-                timestamp     NUMBER NOT NULL,
-                contents      BLOB NOT NULL
-            )",
-            &[],
-        )?;
+    /// Save a new upload into the database, taking a reference to its blob.
+    /// If identical contents are already stored under another key the upload is pointed at that blob instead,
+    /// and the key of the newly stored blob is returned so that it can be removed
+    pub fn save_upload(&self, upload: &mut UploadMetadata) -> rusqlite::Result<Option<BlobKey>> {
+        immediate(self, || {
+            let stored = self.reference_blob(&upload.sha256, &upload.blob)?;
+            let redundant = if stored == upload.blob {
+                None
+            } else {
+                Some(std::mem::replace(&mut upload.blob, stored))
+            };
+
+            self.execute(
+                "INSERT INTO uploads (id, filename, size, timestamp, blob, sha256, owner, expires_at, max_views, password, visibility, content_type)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                &[
+                    &upload.id,
+                    &upload.filename,
+                    &(upload.size as i64),
+                    &upload.timestamp.timestamp(),
+                    &upload.blob,
+                    &upload.sha256,
+                    &upload.owner,
+                    &upload.expires_at.map(|expires_at| expires_at.timestamp()),
+                    &upload.max_views,
+                    &upload.password,
+                    &upload.visibility,
+                    &upload.content_type,
+                ],
+            )?;
+
+            Ok(redundant)
+        })
+    }
 
-        Ok(())
+    /// Get an upload from the database, using its id
+    pub fn get_upload_metatdata(&self, id: &ID) -> rusqlite::Result<UploadMetadata> {
+        self.query_row_and_then(
+            "SELECT id, filename, size, timestamp, blob, owner, expires_at, max_views, views, password, visibility, sha256, content_type FROM uploads WHERE id=?",
+            &[id],
+            upload_from_row,
+        )
     }
 
-    /// Save a new upload into the database
-    pub fn save_upload(&self, upload: &UploadMetadata, data: &UploadData) -> rusqlite::Result<()> {
-        self.ensure_table_exists()?;
+    /// Get all uploads from the database
+    pub fn get_all_uploads(&self) -> rusqlite::Result<Box<[UploadMetadata]>> {
+        Ok(self
+            .prepare(
+                "SELECT id, filename, size, timestamp, blob, owner, expires_at, max_views, views, password, visibility, sha256, content_type FROM uploads ORDER BY timestamp DESC",
+            )?
+            .query_map::<rusqlite::Result<UploadMetadata>, _>(&[], upload_from_row)?
+            .flatten()
+            .flatten()
+            .collect())
+    }
 
-        self.execute(
-            "INSERT INTO uploads (id, filename, size, timestamp, contents) VALUES (?, ?, ?, ?, ?)",
-            &[
-                &upload.id,
-                &upload.filename,
-                &upload.size.to_ne_bytes().as_ref(),
-                &upload.timestamp.timestamp(),
-                &data,
+    /// Get a page of uploads from the database, sorted and filtered
+    pub fn get_uploads_page(
+        &self,
+        sort: UploadSort,
+        order: Order,
+        filter: &UploadFilter,
+        position: Position,
+    ) -> rusqlite::Result<Page<UploadMetadata>> {
+        PageQuery {
+            table: "uploads",
+            columns: &[
+                "id",
+                "filename",
+                "size",
+                "timestamp",
+                "blob",
+                "owner",
+                "expires_at",
+                "max_views",
+                "views",
+                "password",
+                "visibility",
+                "sha256",
+                "content_type",
             ],
-        )?;
-
-        Ok(())
+            key: sort.key(),
+            order,
+            conditions: filter.conditions(),
+        }
+        .fetch(self, position, upload_from_row)
     }
 
-    /// Get an upload from the database, using its id
-    pub fn get_upload_metatdata(&self, id: &ID) -> rusqlite::Result<UploadMetadata> {
-        self.ensure_table_exists()?;
+    /// Search for uploads by their filename, using an FTS5 query, with the best matches first
+    pub fn search(&self, query: &str) -> rusqlite::Result<Box<[UploadMetadata]>> {
+        Ok(self
+            .prepare(
+                "SELECT uploads.id, uploads.filename, size, timestamp, blob, owner, expires_at, max_views, views, password, visibility, sha256, content_type FROM uploads_search
+                JOIN uploads ON uploads.id = uploads_search.id
+                WHERE uploads_search MATCH ? ORDER BY rank LIMIT ?",
+            )?
+            .query_map::<rusqlite::Result<UploadMetadata>, _>(
+                &[&query, &SEARCH_LIMIT],
+                upload_from_row,
+            )?
+            .flatten()
+            .flatten()
+            .collect())
+    }
 
-        self.query_row_and_then(
-            "SELECT id, filename, size, timestamp FROM uploads WHERE id=?",
-            &[id],
-            |row| {
-                Ok(UploadMetadata {
-                    id: row.get_checked(0)?,
-                    filename: row.get_checked(1)?,
-                    size: u64::from_le_bytes(
-                        (&row.get_checked::<_, Vec<u8>>(2)?[..])
-                            .try_into()
-                            .map_err(|e| {
-                                error!("Error loading filesize: ID: {} Error: {}", id, e);
-
-                                rusqlite::Error::FromSqlConversionFailure(
-                                    0,
-                                    rusqlite::types::Type::Blob,
-                                    Box::new(FromSqlError::InvalidType),
-                                )
-                            })?,
-                    ),
-                    timestamp: NaiveDateTime::from_timestamp(row.get_checked(3)?, 0),
-                })
-            },
-        )
+    /// Get the amount of uploads in the database
+    pub fn count(&self) -> rusqlite::Result<u64> {
+        self.query_row("SELECT COUNT(*) FROM uploads", &[], |row| {
+            row.get::<_, i64>(0) as u64
+        })
     }
 
-    /// Get an upload from the database, using its id
-    pub fn get_upload_data(&self, id: &ID) -> rusqlite::Result<Box<UploadData>> {
-        self.ensure_table_exists()?;
+    /// Get the combined views of all of the uploads in the database
+    pub fn total_views(&self) -> rusqlite::Result<u64> {
+        self.query_row("SELECT COALESCE(SUM(views), 0) FROM uploads", &[], |row| {
+            row.get::<_, i64>(0) as u64
+        })
+    }
 
-        self.query_row_and_then("SELECT contents FROM uploads WHERE id=?", &[id], |row| {
-            Ok(row.get_checked::<_, Vec<_>>(0)?.into_boxed_slice())
+    /// Get the combined size of all of the uploads in the database
+    pub fn total_size(&self) -> rusqlite::Result<u64> {
+        self.query_row("SELECT COALESCE(SUM(size), 0) FROM uploads", &[], |row| {
+            row.get::<_, i64>(0) as u64
         })
     }
 
-    /// Get all uploads from the database
-    pub fn get_all_uploads(&self) -> rusqlite::Result<Box<[UploadMetadata]>> {
-        self.ensure_table_exists()?;
+    /// Get the combined size of the uploads owned by a user
+    pub fn owned_size(&self, owner: &str) -> rusqlite::Result<u64> {
+        self.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM uploads WHERE owner=?",
+            &[&owner],
+            |row| row.get::<_, i64>(0) as u64,
+        )
+    }
 
+    /// Get the combined size of the uploads owned by each user that has any
+    pub fn owned_sizes(&self) -> rusqlite::Result<HashMap<String, u64>> {
         Ok(self
-            .prepare("SELECT id, filename, size, timestamp FROM uploads ORDER BY timestamp DESC")?
-            .query_map::<rusqlite::Result<UploadMetadata>, _>(&[], |row| {
-                Ok(UploadMetadata {
-                    id: row.get_checked(0)?,
-                    filename: row.get_checked(1)?,
-                    size: u64::from_le_bytes(
-                        (&row.get_checked::<_, Vec<u8>>(2)?[..])
-                            .try_into()
-                            .map_err(|e| {
-                                error!("Error loading filesize: {}", e);
-
-                                rusqlite::Error::FromSqlConversionFailure(
-                                    0,
-                                    rusqlite::types::Type::Blob,
-                                    Box::new(FromSqlError::InvalidType),
-                                )
-                            })?,
-                    ),
-                    timestamp: NaiveDateTime::from_timestamp(row.get_checked(3)?, 0),
-                })
+            .prepare("SELECT owner, SUM(size) FROM uploads WHERE owner IS NOT NULL GROUP BY owner")?
+            .query_map::<rusqlite::Result<_>, _>(&[], |row| {
+                Ok((row.get_checked(0)?, row.get_checked::<_, i64>(1)? as u64))
             })?
             .flatten()
             .flatten()
             .collect())
     }
 
-    /// Delete an existing upload
-    pub fn delete_upload(&self, id: &ID) -> rusqlite::Result<()> {
-        self.ensure_table_exists()?;
+    /// Delete an existing upload, returning the keys of its blob and thumbnail if that was the last reference to them
+    pub fn delete_upload(&self, id: &ID) -> rusqlite::Result<Vec<BlobKey>> {
+        immediate(self, || {
+            let sha256: String =
+                self.query_row("SELECT sha256 FROM uploads WHERE id=?", &[id], |row| {
+                    row.get(0)
+                })?;
 
-        self.execute("DELETE FROM uploads WHERE id=?", &[id])?;
+            self.execute("DELETE FROM uploads WHERE id=?", &[id])?;
 
-        Ok(())
+            self.release_blob(&sha256)
+        })
+    }
+
+    /// Get the key of the thumbnail made for the blob with the given hash, if one has been made
+    pub fn get_thumbnail(&self, sha256: &str) -> rusqlite::Result<Option<BlobKey>> {
+        match self.query_row(
+            "SELECT thumbnails.key FROM blobs
+            JOIN blobs AS thumbnails ON thumbnails.sha256 = blobs.thumbnail
+            WHERE blobs.sha256=?",
+            &[&sha256],
+            |row| row.get(0),
+        ) {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Save the thumbnail made for the blob with the given hash, returning the keys of the stored blobs
+    /// that are not needed, either because the thumbnail was already stored or another one was saved first
+    pub fn save_thumbnail(
+        &self,
+        sha256: &str,
+        thumbnail: &StoredBlob,
+    ) -> rusqlite::Result<Vec<BlobKey>> {
+        immediate(self, || {
+            let stored = self.reference_blob(&thumbnail.sha256, &thumbnail.key)?;
+            let mut unused = if stored == thumbnail.key {
+                Vec::new()
+            } else {
+                vec![thumbnail.key.clone()]
+            };
+
+            let changed = self.execute(
+                "UPDATE blobs SET thumbnail=? WHERE sha256=? AND thumbnail IS NULL",
+                &[&thumbnail.sha256, &sha256],
+            )?;
+
+            // The blob already has a thumbnail or has been deleted in the meantime
+            if changed == 0 {
+                unused.extend(self.release_blob(&thumbnail.sha256)?);
+            }
+
+            Ok(unused)
+        })
+    }
+
+    /// Helper fn to take a reference to a stored blob, returning the key that its contents are kept under,
+    /// which is that of the existing blob if identical contents were already stored
+    fn reference_blob(&self, sha256: &str, key: &str) -> rusqlite::Result<BlobKey> {
+        self.execute(
+            "INSERT OR IGNORE INTO blobs (sha256, key, refs) VALUES (?, ?, 0)",
+            &[&sha256, &key],
+        )?;
+        self.execute(
+            "UPDATE blobs SET refs = refs + 1 WHERE sha256=?",
+            &[&sha256],
+        )?;
+
+        self.query_row("SELECT key FROM blobs WHERE sha256=?", &[&sha256], |row| {
+            row.get(0)
+        })
+    }
+
+    /// Helper fn to release a reference to a stored blob, returning the keys of the blob and its thumbnail
+    /// if nothing references them anymore
+    fn release_blob(&self, sha256: &str) -> rusqlite::Result<Vec<BlobKey>> {
+        self.execute(
+            "UPDATE blobs SET refs = refs - 1 WHERE sha256=?",
+            &[&sha256],
+        )?;
+
+        let (key, references, thumbnail): (BlobKey, i64, Option<String>) = self.query_row(
+            "SELECT key, refs, thumbnail FROM blobs WHERE sha256=?",
+            &[&sha256],
+            |row| (row.get(0), row.get(1), row.get(2)),
+        )?;
+
+        if references > 0 {
+            return Ok(Vec::new());
+        }
+
+        self.execute("DELETE FROM blobs WHERE sha256=?", &[&sha256])?;
+
+        let mut released = vec![key];
+        // Thumbnails are smaller than a thumbnail themselves, so they never have one of their own
+        if let Some(thumbnail) = thumbnail {
+            released.extend(self.release_blob(&thumbnail)?);
+        }
+
+        Ok(released)
+    }
+
+    /// Set or remove the hashed password needed to view an upload, returning if the upload exists
+    pub fn set_password(&self, id: &ID, password: Option<&str>) -> rusqlite::Result<bool> {
+        Ok(self.execute("UPDATE uploads SET password=? WHERE id=?", &[&password, id])? > 0)
+    }
+
+    /// Change who can view an upload, returning if the upload exists
+    pub fn set_visibility(&self, id: &ID, visibility: Visibility) -> rusqlite::Result<bool> {
+        Ok(self.execute(
+            "UPDATE uploads SET visibility=? WHERE id=?",
+            &[&visibility, id],
+        )? > 0)
+    }
+
+    /// Count a view of an upload, using up one of its remaining views if it has a limited amount
+    pub fn view(&self, id: &ID) -> rusqlite::Result<Access> {
+        immediate(self, || {
+            let changed = self.execute(
+                "UPDATE uploads SET views = views + 1, max_views = max_views - 1
+                WHERE id=? AND (max_views IS NULL OR max_views > 0)",
+                &[id],
+            )?;
+
+            if changed == 0 {
+                return Ok(Access::Exhausted);
+            }
+
+            let left: Option<u32> =
+                self.query_row("SELECT max_views FROM uploads WHERE id=?", &[id], |row| {
+                    row.get(0)
+                })?;
+
+            Ok(if left == Some(0) {
+                Access::Last
+            } else {
+                Access::Allowed
+            })
+        })
+    }
+
+    /// Delete all uploads that have expired or run out of views,
+    /// returning the keys of the blobs that are no longer referenced.
+    /// Uploads that fail to be deleted are skipped, so that the blobs released before them are still returned
+    pub fn delete_expired(&self, now: NaiveDateTime) -> rusqlite::Result<Vec<BlobKey>> {
+        let expired = self
+            .prepare("SELECT id FROM uploads WHERE expires_at <= ? OR max_views = 0")?
+            .query_map::<rusqlite::Result<ID>, _>(&[&now.timestamp()], |row| row.get_checked(0))?
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let mut blobs = Vec::new();
+        for id in expired {
+            match self.delete_upload(&id) {
+                Ok(released) => blobs.extend(released),
+                Err(e) => error!("Error deleting expired upload: ID: {} Error: {}", id, e),
+            }
+        }
+
+        Ok(blobs)
     }
 }
 
@@ -185,6 +547,22 @@ pub struct Link {
     pub uri: String,
     /// The timestamp of when the link was created
     pub timestamp: NaiveDateTime,
+    /// The username of the user that created the link
+    pub owner: Option<String>,
+    /// When the link expires and should no longer be followed
+    pub expires_at: Option<NaiveDateTime>,
+    /// The amount of times the link can still be followed before it is deleted
+    pub max_hits: Option<u32>,
+    /// The argon2 hash of the password needed to follow the link, if it is protected
+    pub password: Option<String>,
+}
+
+impl Link {
+    /// Helper fn to check if a link has expired
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
 }
 
 /// The amount of hits on a link
@@ -193,90 +571,440 @@ pub type LinkHits = u32;
 /// The type returned from listing a link
 pub type LinkListing = (Link, LinkHits);
 
+/// Helper fn to read a link and its hits from a row with the columns
+/// `id, uri, timestamp, hits, owner, expires_at, max_hits, password` in that order
+fn link_from_row(row: &Row) -> rusqlite::Result<LinkListing> {
+    Ok((
+        Link {
+            id: row.get_checked(0)?,
+            uri: row.get_checked(1)?,
+            timestamp: NaiveDateTime::from_timestamp(row.get_checked(2)?, 0),
+            owner: row.get_checked(4)?,
+            expires_at: row
+                .get_checked::<_, Option<i64>>(5)?
+                .map(|expires_at| NaiveDateTime::from_timestamp(expires_at, 0)),
+            max_hits: row.get_checked(6)?,
+            password: row.get_checked(7)?,
+        },
+        row.get_checked(3)?,
+    ))
+}
+
 impl<'a> LinkTable<'a> {
-    /// Method to create the table if it does not exist
-    fn ensure_table_exists(&self) -> rusqlite::Result<()> {
+    /// Save a link into the database
+    pub fn save_link(&self, link: &Link) -> rusqlite::Result<()> {
         self.execute(
-            "CREATE TABLE IF NOT EXISTS links (
-                id          BLOB PRIMARY KEY NOT NULL,
-                uri         TEXT NOT NULL,
-                timestamp   NUMBER NOT NULL,
-                hits        NUMBER NOT NULL
-            )",
-            &[],
+            "INSERT INTO links (id, uri, timestamp, hits, owner, expires_at, max_hits, password)
+            VALUES (?, ?, ?, 0, ?, ?, ?, ?)",
+            &[
+                &link.id,
+                &link.uri.to_string(),
+                &link.timestamp.timestamp(),
+                &link.owner,
+                &link.expires_at.map(|expires_at| expires_at.timestamp()),
+                &link.max_hits,
+                &link.password,
+            ],
         )?;
 
         Ok(())
     }
 
-    /// Save a link into the database
-    pub fn save_link(&self, link: &Link) -> rusqlite::Result<()> {
-        self.ensure_table_exists()?;
+    /// Get a link from the database, using its id
+    pub fn get_link(&self, id: &ID) -> rusqlite::Result<LinkListing> {
+        self.query_row_and_then(
+            "SELECT id, uri, timestamp, hits, owner, expires_at, max_hits, password FROM links WHERE id=?",
+            &[id],
+            link_from_row,
+        )
+    }
+
+    /// Get all links from the database
+    pub fn get_all_links(&self) -> rusqlite::Result<Box<[LinkListing]>> {
+        Ok(self
+            .prepare("SELECT id, uri, timestamp, hits, owner, expires_at, max_hits, password FROM links ORDER BY timestamp DESC")?
+            .query_map::<rusqlite::Result<_>, _>(&[], link_from_row)?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get a page of links from the database, sorted and filtered
+    pub fn get_links_page(
+        &self,
+        sort: LinkSort,
+        order: Order,
+        filter: &LinkFilter,
+        position: Position,
+    ) -> rusqlite::Result<Page<LinkListing>> {
+        PageQuery {
+            table: "links",
+            columns: &[
+                "id",
+                "uri",
+                "timestamp",
+                "hits",
+                "owner",
+                "expires_at",
+                "max_hits",
+                "password",
+            ],
+            key: sort.key(),
+            order,
+            conditions: filter.conditions(),
+        }
+        .fetch(self, position, link_from_row)
+    }
+
+    /// Search for links by their uri, using an FTS5 query, with the best matches first
+    pub fn search(&self, query: &str) -> rusqlite::Result<Box<[LinkListing]>> {
+        Ok(self
+            .prepare(
+                "SELECT links.id, links.uri, timestamp, hits, owner, expires_at, max_hits, password FROM links_search
+                JOIN links ON links.id = links_search.id
+                WHERE links_search MATCH ? ORDER BY rank LIMIT ?",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&query, &SEARCH_LIMIT], link_from_row)?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get the amount of links in the database
+    pub fn count(&self) -> rusqlite::Result<u64> {
+        self.query_row("SELECT COUNT(*) FROM links", &[], |row| {
+            row.get::<_, i64>(0) as u64
+        })
+    }
+
+    /// Get the combined amount of hits on all of the links in the database
+    pub fn total_hits(&self) -> rusqlite::Result<LinkHits> {
+        self.query_row("SELECT COALESCE(SUM(hits), 0) FROM links", &[], |row| {
+            row.get(0)
+        })
+    }
+
+    /// Set or remove the hashed password needed to follow a link, returning if the link exists
+    pub fn set_password(&self, id: &ID, password: Option<&str>) -> rusqlite::Result<bool> {
+        Ok(self.execute("UPDATE links SET password=? WHERE id=?", &[&password, id])? > 0)
+    }
+
+    /// Count a hit on a link, using up one of its remaining hits if it has a limited amount
+    pub fn hit(&self, id: &ID) -> rusqlite::Result<Access> {
+        immediate(self, || {
+            let changed = self.execute(
+                "UPDATE links SET hits = hits + 1, max_hits = max_hits - 1
+                WHERE id=? AND (max_hits IS NULL OR max_hits > 0)",
+                &[id],
+            )?;
+
+            if changed == 0 {
+                return Ok(Access::Exhausted);
+            }
+
+            let left: Option<u32> =
+                self.query_row("SELECT max_hits FROM links WHERE id=?", &[id], |row| {
+                    row.get(0)
+                })?;
+
+            Ok(if left == Some(0) {
+                Access::Last
+            } else {
+                Access::Allowed
+            })
+        })
+    }
+
+    /// Delete an existing link
+    pub fn delete_link(&self, id: &ID) -> rusqlite::Result<()> {
+        self.execute("DELETE FROM links WHERE id=?", &[id])?;
+
+        Ok(())
+    }
+
+    /// Delete all links that have expired or run out of hits, returning the amount that were deleted
+    pub fn delete_expired(&self, now: NaiveDateTime) -> rusqlite::Result<usize> {
+        Ok(self.execute(
+            "DELETE FROM links WHERE expires_at <= ? OR max_hits = 0",
+            &[&now.timestamp()],
+        )? as usize)
+    }
+}
+
+/// Connection to the sessions table
+#[derive(Debug, Deref)]
+pub struct SessionTable<'a>(&'a Connection);
+
+/// A session of a logged in user that is stored in the sessions table
+pub struct Session {
+    /// The hash of the session's token, so that the token itself is never stored
+    pub id: String,
+    /// The username of the user that the session belongs to
+    pub username: String,
+    /// The timestamp of when the session was created
+    pub created: NaiveDateTime,
+    /// The timestamp of when the session expires
+    pub expires: NaiveDateTime,
+    /// The user agent of the client that created the session
+    pub user_agent: Option<String>,
+}
 
+impl<'a> SessionTable<'a> {
+    /// Save a new session into the database
+    pub fn save_session(&self, session: &Session) -> rusqlite::Result<()> {
         self.execute(
-            "INSERT INTO links (id, uri, timestamp, hits) VALUES (?, ?, ?, 0)",
-            &[&link.id, &link.uri.to_string(), &link.timestamp.timestamp()],
+            "INSERT INTO sessions (id, username, created, expires, user_agent) VALUES (?, ?, ?, ?, ?)",
+            &[
+                &session.id,
+                &session.username,
+                &session.created.timestamp(),
+                &session.expires.timestamp(),
+                &session.user_agent,
+            ],
         )?;
 
         Ok(())
     }
 
-    /// Get a link from the database, using its id
-    pub fn get_link(&self, id: &ID) -> rusqlite::Result<LinkListing> {
-        self.ensure_table_exists()?;
-
+    /// Get a session from the database using its id, if it has not expired
+    pub fn get_session(&self, id: &str, now: NaiveDateTime) -> rusqlite::Result<Session> {
         self.query_row_and_then(
-            "SELECT id, uri, timestamp, hits FROM links WHERE id=?",
-            &[id],
+            "SELECT id, username, created, expires, user_agent FROM sessions WHERE id=? AND expires > ?",
+            &[&id, &now.timestamp()],
             |row| {
-                Ok((
-                    Link {
-                        id: row.get_checked(0)?,
-                        uri: row.get_checked(1)?,
-                        timestamp: NaiveDateTime::from_timestamp(row.get_checked(2)?, 0),
-                    },
-                    row.get_checked(3)?,
-                ))
+                Ok(Session {
+                    id: row.get_checked(0)?,
+                    username: row.get_checked(1)?,
+                    created: NaiveDateTime::from_timestamp(row.get_checked(2)?, 0),
+                    expires: NaiveDateTime::from_timestamp(row.get_checked(3)?, 0),
+                    user_agent: row.get_checked(4)?,
+                })
             },
         )
     }
 
-    /// Get all links from the database
-    pub fn get_all_links(&self) -> rusqlite::Result<Box<[LinkListing]>> {
-        self.ensure_table_exists()?;
-
+    /// Get all of the sessions belonging to a user that have not expired
+    pub fn get_user_sessions(
+        &self,
+        username: &str,
+        now: NaiveDateTime,
+    ) -> rusqlite::Result<Box<[Session]>> {
         Ok(self
-            .prepare("SELECT id, uri, timestamp, hits FROM links ORDER BY timestamp DESC")?
-            .query_map::<rusqlite::Result<_>, _>(&[], |row| {
-                Ok((
-                    Link {
-                        id: row.get_checked(0)?,
-                        uri: row.get_checked(1)?,
-                        timestamp: NaiveDateTime::from_timestamp(row.get_checked(2)?, 0),
-                    },
-                    row.get_checked(3)?,
-                ))
+            .prepare(
+                "SELECT id, username, created, expires, user_agent FROM sessions
+                WHERE username=? AND expires > ? ORDER BY created DESC",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&username, &now.timestamp()], |row| {
+                Ok(Session {
+                    id: row.get_checked(0)?,
+                    username: row.get_checked(1)?,
+                    created: NaiveDateTime::from_timestamp(row.get_checked(2)?, 0),
+                    expires: NaiveDateTime::from_timestamp(row.get_checked(3)?, 0),
+                    user_agent: row.get_checked(4)?,
+                })
             })?
             .flatten()
             .flatten()
             .collect())
     }
 
-    /// Get the amount of views that a link has gotten
-    pub fn hit(&self, id: &ID) -> rusqlite::Result<()> {
-        self.ensure_table_exists()?;
+    /// End a session, such as when its user logs out
+    pub fn end_session(&self, id: &str) -> rusqlite::Result<()> {
+        self.execute("DELETE FROM sessions WHERE id=?", &[&id])?;
+
+        Ok(())
+    }
+
+    /// Delete a session belonging to a user, returning if it existed
+    pub fn delete_session(&self, id: &str, username: &str) -> rusqlite::Result<bool> {
+        Ok(self.execute(
+            "DELETE FROM sessions WHERE id=? AND username=?",
+            &[&id, &username],
+        )? > 0)
+    }
 
-        self.execute("UPDATE links SET hits = hits + 1 WHERE id=?", &[id])?;
+    /// Delete all of the sessions that have expired
+    pub fn delete_expired(&self, now: NaiveDateTime) -> rusqlite::Result<()> {
+        self.execute(
+            "DELETE FROM sessions WHERE expires <= ?",
+            &[&now.timestamp()],
+        )?;
 
         Ok(())
     }
+}
 
-    /// Delete an existing link
-    pub fn delete_link(&self, id: &ID) -> rusqlite::Result<()> {
-        self.ensure_table_exists()?;
+/// Connection to the access log table
+#[derive(Debug, Deref)]
+pub struct AccessTable<'a>(&'a Connection);
+
+/// The kinds of resources that can be accessed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceKind {
+    /// An upload that was viewed
+    Upload,
+    /// A link that was followed
+    Link,
+}
 
-        self.execute("DELETE FROM links WHERE id=?", &[id])?;
+impl ResourceKind {
+    /// The name of the kind as it is stored in the database
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            ResourceKind::Upload => "upload",
+            ResourceKind::Link => "link",
+        }
+    }
+}
+
+/// A single access to an upload or link that is stored in the access log
+pub struct AccessRecord {
+    /// The id of the upload or link that was accessed
+    pub resource: ID,
+    /// The kind of resource that was accessed
+    pub kind: ResourceKind,
+    /// The timestamp of when the access happened
+    pub timestamp: NaiveDateTime,
+    /// The page that linked to the resource
+    pub referrer: Option<String>,
+    /// The user agent of the visitor
+    pub user_agent: Option<String>,
+    /// The salted hash of the visitor's ip address
+    pub ip_hash: Option<String>,
+}
+
+/// The amount of times something was accessed
+pub type AccessCount = u64;
+
+/// An upload or link ranked by how many times it was accessed, along with its filename or uri
+pub type TopResource = (ID, String, AccessCount);
+
+impl<'a> AccessTable<'a> {
+    /// Add an access to the log
+    pub fn record(&self, access: &AccessRecord) -> rusqlite::Result<()> {
+        self.execute(
+            "INSERT INTO accesses (resource, kind, timestamp, referrer, user_agent, ip_hash)
+            VALUES (?, ?, ?, ?, ?, ?)",
+            &[
+                &access.resource,
+                &access.kind.name(),
+                &access.timestamp.timestamp(),
+                &access.referrer,
+                &access.user_agent,
+                &access.ip_hash,
+            ],
+        )?;
 
         Ok(())
     }
+
+    /// Get the amount of accesses on each day since the given time, leaving out days without any
+    pub fn daily_counts(
+        &self,
+        since: NaiveDateTime,
+    ) -> rusqlite::Result<Box<[(NaiveDate, AccessCount)]>> {
+        Ok(self
+            .prepare(
+                "SELECT timestamp / 86400 AS day, COUNT(*) FROM accesses
+                WHERE timestamp >= ? GROUP BY day ORDER BY day",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&since.timestamp()], |row| {
+                Ok((
+                    NaiveDateTime::from_timestamp(row.get_checked::<_, i64>(0)? * 86400, 0).date(),
+                    row.get_checked::<_, i64>(1)? as AccessCount,
+                ))
+            })?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get the uploads that have been viewed the most since the given time
+    pub fn top_uploads(
+        &self,
+        since: NaiveDateTime,
+        limit: u32,
+    ) -> rusqlite::Result<Box<[TopResource]>> {
+        Ok(self
+            .prepare(
+                "SELECT uploads.id, uploads.filename, COUNT(*) AS views FROM accesses
+                JOIN uploads ON uploads.id = accesses.resource
+                WHERE accesses.timestamp >= ? GROUP BY uploads.id ORDER BY views DESC LIMIT ?",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&since.timestamp(), &limit], |row| {
+                Ok((
+                    row.get_checked(0)?,
+                    row.get_checked(1)?,
+                    row.get_checked::<_, i64>(2)? as AccessCount,
+                ))
+            })?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get the links that have been followed the most since the given time
+    pub fn top_links(
+        &self,
+        since: NaiveDateTime,
+        limit: u32,
+    ) -> rusqlite::Result<Box<[TopResource]>> {
+        Ok(self
+            .prepare(
+                "SELECT links.id, links.uri, COUNT(*) AS hits FROM accesses
+                JOIN links ON links.id = accesses.resource
+                WHERE accesses.timestamp >= ? GROUP BY links.id ORDER BY hits DESC LIMIT ?",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&since.timestamp(), &limit], |row| {
+                Ok((
+                    row.get_checked(0)?,
+                    row.get_checked(1)?,
+                    row.get_checked::<_, i64>(2)? as AccessCount,
+                ))
+            })?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get the pages that have linked to uploads and links the most since the given time
+    pub fn top_referrers(
+        &self,
+        since: NaiveDateTime,
+        limit: u32,
+    ) -> rusqlite::Result<Box<[(String, AccessCount)]>> {
+        Ok(self
+            .prepare(
+                "SELECT referrer, COUNT(*) AS accesses FROM accesses
+                WHERE timestamp >= ? AND referrer IS NOT NULL
+                GROUP BY referrer ORDER BY accesses DESC LIMIT ?",
+            )?
+            .query_map::<rusqlite::Result<_>, _>(&[&since.timestamp(), &limit], |row| {
+                Ok((
+                    row.get_checked(0)?,
+                    row.get_checked::<_, i64>(1)? as AccessCount,
+                ))
+            })?
+            .flatten()
+            .flatten()
+            .collect())
+    }
+
+    /// Get the amount of different visitors since the given time, going by their hashed ip address
+    pub fn unique_visitors(&self, since: NaiveDateTime) -> rusqlite::Result<AccessCount> {
+        self.query_row(
+            "SELECT COUNT(DISTINCT ip_hash) FROM accesses WHERE timestamp >= ?",
+            &[&since.timestamp()],
+            |row| row.get::<_, i64>(0) as AccessCount,
+        )
+    }
+
+    /// Delete all of the accesses from before the given time, returning the amount that were deleted
+    pub fn delete_before(&self, cutoff: NaiveDateTime) -> rusqlite::Result<usize> {
+        Ok(self.execute(
+            "DELETE FROM accesses WHERE timestamp < ?",
+            &[&cutoff.timestamp()],
+        )? as usize)
+    }
 }
@@ -0,0 +1,644 @@
+//! Versioned migrations for the database schema
+
+use super::Database;
+use crate::{
+    id::ID,
+    sniff::{self, SNIFF_LENGTH},
+    storage::{sha256, BlobKey, BlobReader, Storage, StoredBlob},
+};
+use derive_more::{Display, From};
+use rocket::fairing::{AdHoc, Fairing};
+use rusqlite::{
+    types::{FromSqlError, Type},
+    Connection,
+};
+use std::{
+    convert::TryInto,
+    error::Error,
+    io::{self, Read},
+    sync::Mutex,
+};
+
+/// An error that occurred while migrating the database
+#[derive(Debug, Display, From)]
+pub enum MigrationError {
+    /// An error from the database
+    #[display(fmt = "Database error: {}", _0)]
+    Database(rusqlite::Error),
+    /// An error from the storage backend
+    #[display(fmt = "Storage error: {}", _0)]
+    Storage(io::Error),
+}
+
+impl Error for MigrationError {}
+
+/// A single step in the evolution of the database schema
+pub struct Migration {
+    /// A short description of what the migration does
+    pub description: &'static str,
+    /// The function that applies the migration
+    apply: fn(&Connection, &dyn Storage) -> Result<(), MigrationError>,
+}
+
+/// Every migration in the order that they are applied. The schema version
+/// of a database is the amount of these migrations that have been applied to it
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create the uploads and links tables",
+        apply: create_tables,
+    },
+    Migration {
+        description: "Move the contents of uploads into the storage backend",
+        apply: move_contents,
+    },
+    Migration {
+        description: "Store the size of uploads as an integer",
+        apply: integer_sizes,
+    },
+    Migration {
+        description: "Record the owner of uploads and links",
+        apply: owners,
+    },
+    Migration {
+        description: "Create the sessions table",
+        apply: sessions,
+    },
+    Migration {
+        description: "Index the columns that uploads and links are sorted by",
+        apply: sort_indexes,
+    },
+    Migration {
+        description: "Create the full text search indexes",
+        apply: search_indexes,
+    },
+    Migration {
+        description: "Record when uploads and links expire",
+        apply: expiry,
+    },
+    Migration {
+        description: "Limit the amount of times uploads and links can be accessed",
+        apply: access_limits,
+    },
+    Migration {
+        description: "Count views of uploads and log accesses to uploads and links",
+        apply: access_log,
+    },
+    Migration {
+        description: "Protect uploads and links with passwords",
+        apply: passwords,
+    },
+    Migration {
+        description: "Let uploads be unlisted or private",
+        apply: visibility,
+    },
+    Migration {
+        description: "Store identical uploads once with a reference count",
+        apply: blob_references,
+    },
+    Migration {
+        description: "Keep track of the thumbnails made for images",
+        apply: thumbnails,
+    },
+    Migration {
+        description: "Store the content type detected from each upload's contents",
+        apply: content_types,
+    },
+];
+
+/// Get the version of the schema that the database is currently at
+///
+/// # Errors
+/// - If the version could not be read from the database
+pub fn schema_version(connection: &Connection) -> rusqlite::Result<usize> {
+    let version: i64 = connection.query_row("PRAGMA user_version", &[], |row| row.get(0))?;
+
+    Ok(version as usize)
+}
+
+/// Get the migrations that have not yet been applied to the database, along with the
+/// schema version that each will bring the database to
+///
+/// # Errors
+/// - If the version could not be read from the database
+pub fn pending(connection: &Connection) -> rusqlite::Result<Vec<(usize, &'static Migration)>> {
+    let version = schema_version(connection)?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(version)
+        .map(|(index, migration)| (index + 1, migration))
+        .collect())
+}
+
+/// Apply all pending migrations to the database, returning the amount that were applied
+///
+/// # Errors
+/// - If any of the migrations fail, in which case that migration is rolled back and the blobs it stored are removed
+pub fn migrate(connection: &Connection, storage: &dyn Storage) -> Result<usize, MigrationError> {
+    let pending = pending(connection)?;
+
+    for (version, migration) in &pending {
+        info!(
+            "Migrating database to version {}: {}",
+            version, migration.description
+        );
+
+        connection.execute_batch("BEGIN")?;
+
+        let staged = Staged::new(storage);
+        let result = (migration.apply)(connection, &staged)
+            .and_then(|()| {
+                connection
+                    .execute_batch(&format!("PRAGMA user_version = {}", version))
+                    .map_err(MigrationError::from)
+            })
+            .and_then(|()| {
+                connection
+                    .execute_batch("COMMIT")
+                    .map_err(MigrationError::from)
+            });
+
+        if let Err(e) = result {
+            // The transaction may already be gone if committing it failed
+            if let Err(e) = connection.execute_batch("ROLLBACK") {
+                warn!("Failed to roll back the migration: {}", e);
+            }
+            staged.discard();
+
+            return Err(e);
+        }
+
+        staged.finish();
+    }
+
+    if !pending.is_empty() {
+        // Reclaim any space freed up by the migrations
+        connection.execute_batch("VACUUM")?;
+    }
+
+    Ok(pending.len())
+}
+
+/// A storage backend that remembers the blobs stored by a migration, so that they can be removed
+/// if the migration is rolled back and nothing in the database points to them. Blobs are only removed
+/// once the migration has been committed, as the database still points to them until then
+struct Staged<'a> {
+    /// The storage backend that the blobs are stored in
+    storage: &'a dyn Storage,
+    /// The keys of the blobs that have been stored
+    written: Mutex<Vec<BlobKey>>,
+    /// The keys of the blobs that are to be removed
+    removed: Mutex<Vec<BlobKey>>,
+}
+
+impl<'a> Staged<'a> {
+    /// Start remembering the blobs stored in a storage backend
+    fn new(storage: &'a dyn Storage) -> Self {
+        Staged {
+            storage,
+            written: Mutex::new(Vec::new()),
+            removed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Remove all of the blobs that were removed by the committed migration
+    fn finish(self) {
+        for key in self.removed.into_inner().unwrap() {
+            if let Err(e) = self.storage.delete(&key) {
+                warn!("Failed to remove blob {} after a migration: {}", key, e);
+            }
+        }
+    }
+
+    /// Remove all of the blobs that have been stored
+    fn discard(self) {
+        for key in self.written.into_inner().unwrap() {
+            if let Err(e) = self.storage.delete(&key) {
+                warn!("Failed to remove blob {} of a failed migration: {}", key, e);
+            }
+        }
+    }
+}
+
+impl<'a> Storage for Staged<'a> {
+    fn put(&self, data: &mut dyn Read) -> io::Result<StoredBlob> {
+        let stored = self.storage.put(data)?;
+        self.written.lock().unwrap().push(stored.key.clone());
+
+        Ok(stored)
+    }
+
+    fn open(&self, key: &str) -> io::Result<Box<dyn BlobReader>> {
+        self.storage.open(key)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.removed.lock().unwrap().push(key.to_string());
+
+        Ok(())
+    }
+}
+
+/// A fairing that applies any pending migrations when the server starts
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_attach("Database Migrations", |rocket| {
+        let database = match Database::get_one(&rocket) {
+            Some(database) => database,
+            None => {
+                error!("Failed to connect to the database to apply migrations");
+                return Err(rocket);
+            }
+        };
+        let storage = rocket.state::<Box<dyn Storage>>().unwrap();
+
+        match migrate(&database, storage.as_ref()) {
+            Ok(0) => trace!("Database schema is up to date"),
+            Ok(applied) => info!("Applied {} database migrations", applied),
+            Err(e) => {
+                error!("Failed to migrate the database: {}", e);
+                return Err(rocket);
+            }
+        }
+
+        Ok(rocket)
+    })
+}
+
+/// Create the tables as they were originally laid out
+fn create_tables(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS uploads (
+            id            BLOB PRIMARY KEY NOT NULL,
+            filename      TEXT NOT NULL,
+            size          BLOB NOT NULL,
+            timestamp     NUMBER NOT NULL,
+            contents      BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS links (
+            id          BLOB PRIMARY KEY NOT NULL,
+            uri         TEXT NOT NULL,
+            timestamp   NUMBER NOT NULL,
+            hits        NUMBER NOT NULL
+        );",
+    )?;
+
+    Ok(())
+}
+
+/// Move the contents of uploads out of the database and into the storage backend
+fn move_contents(connection: &Connection, storage: &dyn Storage) -> Result<(), MigrationError> {
+    // Databases created before migrations existed may have already been moved
+    let legacy = connection
+        .prepare("PRAGMA table_info(uploads)")?
+        .query_map::<rusqlite::Result<String>, _>(&[], |row| row.get_checked(1))?
+        .flatten()
+        .flatten()
+        .any(|column| column == "contents");
+
+    if !legacy {
+        return Ok(());
+    }
+
+    connection.execute_batch(
+        "CREATE TABLE uploads_migrated (
+            id            BLOB PRIMARY KEY NOT NULL,
+            filename      TEXT NOT NULL,
+            size          BLOB NOT NULL,
+            timestamp     NUMBER NOT NULL,
+            blob          TEXT NOT NULL
+        );",
+    )?;
+
+    {
+        let mut statement =
+            connection.prepare("SELECT id, filename, size, timestamp, contents FROM uploads")?;
+        let rows = statement.query_map::<rusqlite::Result<_>, _>(&[], |row| {
+            Ok((
+                row.get_checked::<_, ID>(0)?,
+                row.get_checked::<_, String>(1)?,
+                row.get_checked::<_, Vec<u8>>(2)?,
+                row.get_checked::<_, i64>(3)?,
+                row.get_checked::<_, Vec<u8>>(4)?,
+            ))
+        })?;
+
+        for row in rows {
+            let (id, filename, size, timestamp, contents) = row??;
+
+            let blob = storage.put(&mut contents.as_slice())?.key;
+            trace!("Moved contents of upload {} into blob {}", id, blob);
+
+            connection.execute(
+                "INSERT INTO uploads_migrated (id, filename, size, timestamp, blob) VALUES (?, ?, ?, ?, ?)",
+                &[&id, &filename, &size, &timestamp, &blob],
+            )?;
+        }
+    }
+
+    connection.execute_batch(
+        "DROP TABLE uploads;
+        ALTER TABLE uploads_migrated RENAME TO uploads;",
+    )?;
+
+    Ok(())
+}
+
+/// Convert the sizes of uploads from raw bytes into integers
+fn integer_sizes(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "CREATE TABLE uploads_migrated (
+            id            BLOB PRIMARY KEY NOT NULL,
+            filename      TEXT NOT NULL,
+            size          INTEGER NOT NULL,
+            timestamp     NUMBER NOT NULL,
+            blob          TEXT NOT NULL
+        );",
+    )?;
+
+    {
+        let mut statement = connection.prepare("SELECT id, size FROM uploads")?;
+        let rows = statement.query_map::<rusqlite::Result<_>, _>(&[], |row| {
+            Ok((
+                row.get_checked::<_, ID>(0)?,
+                row.get_checked::<_, Vec<u8>>(1)?,
+            ))
+        })?;
+
+        for row in rows {
+            let (id, size) = row??;
+
+            // The sizes were written in the native byte order of the host that stored them
+            let size = i64::from_ne_bytes(size[..].try_into().map_err(|_| {
+                error!("Invalid size for upload: ID: {} Size: {:?}", id, size);
+
+                rusqlite::Error::FromSqlConversionFailure(
+                    1,
+                    Type::Blob,
+                    Box::new(FromSqlError::InvalidType),
+                )
+            })?);
+
+            connection.execute(
+                "INSERT INTO uploads_migrated (id, filename, size, timestamp, blob)
+                SELECT id, filename, ?, timestamp, blob FROM uploads WHERE id=?",
+                &[&size, &id],
+            )?;
+        }
+    }
+
+    connection.execute_batch(
+        "DROP TABLE uploads;
+        ALTER TABLE uploads_migrated RENAME TO uploads;",
+    )?;
+
+    Ok(())
+}
+
+/// Add the user that created them to uploads and links
+fn owners(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN owner TEXT;
+        ALTER TABLE links ADD COLUMN owner TEXT;",
+    )?;
+
+    Ok(())
+}
+
+/// Store the sessions of logged in users
+fn sessions(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "CREATE TABLE sessions (
+            id            TEXT PRIMARY KEY NOT NULL,
+            username      TEXT NOT NULL,
+            created       NUMBER NOT NULL,
+            expires       NUMBER NOT NULL,
+            user_agent    TEXT
+        );",
+    )?;
+
+    Ok(())
+}
+
+/// Index the sort keys of uploads and links so that pages can be found without scanning the tables
+fn sort_indexes(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "CREATE INDEX uploads_timestamp ON uploads (timestamp, id);
+        CREATE INDEX uploads_size ON uploads (size, id);
+        CREATE INDEX uploads_name ON uploads (lower(filename), id);
+        CREATE INDEX links_timestamp ON links (timestamp, id);
+        CREATE INDEX links_hits ON links (hits, id);
+        CREATE INDEX links_name ON links (lower(uri), id);",
+    )?;
+
+    Ok(())
+}
+
+/// Index upload filenames and link uris for full text search, using triggers to keep the indexes
+/// in sync. Migrations that rebuild the uploads or links tables drop these triggers, so must
+/// create them again
+fn search_indexes(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "CREATE VIRTUAL TABLE uploads_search USING fts5(id UNINDEXED, filename);
+        INSERT INTO uploads_search (id, filename) SELECT id, filename FROM uploads;
+        CREATE TRIGGER uploads_search_insert AFTER INSERT ON uploads BEGIN
+            INSERT INTO uploads_search (id, filename) VALUES (new.id, new.filename);
+        END;
+        CREATE TRIGGER uploads_search_update AFTER UPDATE OF filename ON uploads BEGIN
+            UPDATE uploads_search SET filename = new.filename WHERE id = old.id;
+        END;
+        CREATE TRIGGER uploads_search_delete AFTER DELETE ON uploads BEGIN
+            DELETE FROM uploads_search WHERE id = old.id;
+        END;
+
+        CREATE VIRTUAL TABLE links_search USING fts5(id UNINDEXED, uri);
+        INSERT INTO links_search (id, uri) SELECT id, uri FROM links;
+        CREATE TRIGGER links_search_insert AFTER INSERT ON links BEGIN
+            INSERT INTO links_search (id, uri) VALUES (new.id, new.uri);
+        END;
+        CREATE TRIGGER links_search_update AFTER UPDATE OF uri ON links BEGIN
+            UPDATE links_search SET uri = new.uri WHERE id = old.id;
+        END;
+        CREATE TRIGGER links_search_delete AFTER DELETE ON links BEGIN
+            DELETE FROM links_search WHERE id = old.id;
+        END;",
+    )?;
+
+    Ok(())
+}
+
+/// Add an optional expiry time to uploads and links
+fn expiry(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN expires_at NUMBER;
+        ALTER TABLE links ADD COLUMN expires_at NUMBER;
+        CREATE INDEX uploads_expires_at ON uploads (expires_at) WHERE expires_at IS NOT NULL;
+        CREATE INDEX links_expires_at ON links (expires_at) WHERE expires_at IS NOT NULL;",
+    )?;
+
+    Ok(())
+}
+
+/// Add an optional limit to the amount of views of uploads and hits of links
+fn access_limits(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN max_views INTEGER;
+        ALTER TABLE links ADD COLUMN max_hits INTEGER;",
+    )?;
+
+    Ok(())
+}
+
+/// Count the views of uploads, and log each access to uploads and links for the stats page
+fn access_log(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN views INTEGER NOT NULL DEFAULT 0;
+        CREATE TABLE accesses (
+            resource      BLOB NOT NULL,
+            kind          TEXT NOT NULL,
+            timestamp     NUMBER NOT NULL,
+            referrer      TEXT,
+            user_agent    TEXT,
+            ip_hash       TEXT
+        );
+        CREATE INDEX accesses_timestamp ON accesses (timestamp);",
+    )?;
+
+    Ok(())
+}
+
+/// Add an optional hashed password that is needed to view uploads and follow links
+fn passwords(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN password TEXT;
+        ALTER TABLE links ADD COLUMN password TEXT;",
+    )?;
+
+    Ok(())
+}
+
+/// Add who can view each upload, keeping the existing uploads public
+fn visibility(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';",
+    )?;
+
+    Ok(())
+}
+
+/// Hash the contents of every upload, and count how many uploads reference each blob.
+/// Uploads with identical contents are pointed at the same blob, and the other copies are removed
+fn blob_references(connection: &Connection, storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN sha256 TEXT NOT NULL DEFAULT '';
+        CREATE TABLE blobs (
+            sha256        TEXT PRIMARY KEY NOT NULL,
+            key           TEXT NOT NULL,
+            refs          INTEGER NOT NULL
+        );",
+    )?;
+
+    let blobs = connection
+        .prepare("SELECT blob, COUNT(*) FROM uploads GROUP BY blob")?
+        .query_map::<rusqlite::Result<(BlobKey, i64)>, _>(&[], |row| {
+            Ok((row.get_checked(0)?, row.get_checked(1)?))
+        })?
+        .map(|row| row.and_then(|row| row))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (blob, references) in blobs {
+        // Blobs that can not be read keep their key as their hash, so that their uploads can still be deleted
+        let hash = match storage
+            .open(&blob)
+            .and_then(|mut reader| sha256(&mut reader))
+        {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!(
+                    "Could not read blob to hash its contents: Blob: {} Error: {}",
+                    blob, e
+                );
+                blob.clone()
+            }
+        };
+        trace!("Hashed blob {}: {}", blob, hash);
+
+        connection.execute(
+            "INSERT OR IGNORE INTO blobs (sha256, key, refs) VALUES (?, ?, 0)",
+            &[&hash, &blob],
+        )?;
+        connection.execute(
+            "UPDATE blobs SET refs = refs + ? WHERE sha256=?",
+            &[&references, &hash],
+        )?;
+        let stored: BlobKey =
+            connection.query_row("SELECT key FROM blobs WHERE sha256=?", &[&hash], |row| {
+                row.get(0)
+            })?;
+
+        connection.execute(
+            "UPDATE uploads SET blob=?, sha256=? WHERE blob=?",
+            &[&stored, &hash, &blob],
+        )?;
+
+        // The same contents are already stored under another key
+        if stored != blob {
+            trace!("Replaced blob {} with identical blob {}", blob, stored);
+            storage.delete(&blob)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the hash of the thumbnail made for each blob
+fn thumbnails(connection: &Connection, _storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch("ALTER TABLE blobs ADD COLUMN thumbnail TEXT;")?;
+
+    Ok(())
+}
+
+/// Detect the content type of every upload from the start of its contents
+fn content_types(connection: &Connection, storage: &dyn Storage) -> Result<(), MigrationError> {
+    connection.execute_batch(
+        "ALTER TABLE uploads ADD COLUMN content_type TEXT NOT NULL DEFAULT 'application/octet-stream';",
+    )?;
+
+    let uploads = connection
+        .prepare("SELECT id, filename, blob FROM uploads")?
+        .query_map::<rusqlite::Result<(ID, String, BlobKey)>, _>(&[], |row| {
+            Ok((
+                row.get_checked(0)?,
+                row.get_checked(1)?,
+                row.get_checked(2)?,
+            ))
+        })?
+        .map(|row| row.and_then(|row| row))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (id, filename, blob) in uploads {
+        let mut head = Vec::with_capacity(SNIFF_LENGTH);
+        let read = storage
+            .open(&blob)
+            .and_then(|reader| reader.take(SNIFF_LENGTH as u64).read_to_end(&mut head));
+
+        // Uploads that can not be read keep the default content type, rather than stopping the server from starting
+        if let Err(e) = read {
+            warn!(
+                "Could not read upload to detect its content type: ID: {} Blob: {} Error: {}",
+                id, blob, e
+            );
+            continue;
+        }
+
+        let content_type = sniff::detect(&head, &filename).to_string();
+        trace!("Detected content type of upload {}: {}", id, content_type);
+
+        connection.execute(
+            "UPDATE uploads SET content_type=? WHERE id=?",
+            &[&content_type, &id],
+        )?;
+    }
+
+    Ok(())
+}
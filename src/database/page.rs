@@ -0,0 +1,542 @@
+//! Keyset pagination, sorting and filtering for listing uploads and links
+
+use crate::id::ID;
+use chrono::{Duration, NaiveDate};
+use rocket::{http::RawStr, request::FromFormValue};
+use rusqlite::{
+    types::{ToSql, Value},
+    Connection, Row,
+};
+use std::{convert::TryFrom, fmt};
+
+/// The amount of items shown on each page of a listing
+pub const PAGE_SIZE: usize = 50;
+
+/// The direction to sort a listing in
+#[derive(Debug, Clone, Copy, PartialEq, FromFormValue)]
+pub enum Order {
+    /// Smallest, oldest or first alphabetically first
+    #[form(value = "asc")]
+    Ascending,
+    /// Largest, newest or last alphabetically first
+    #[form(value = "desc")]
+    Descending,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Descending
+    }
+}
+
+impl Order {
+    /// The value of the order as used in query strings
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Ascending => "asc",
+            Order::Descending => "desc",
+        }
+    }
+
+    /// The opposite order, used to walk backwards through a listing
+    fn reverse(self) -> Self {
+        match self {
+            Order::Ascending => Order::Descending,
+            Order::Descending => Order::Ascending,
+        }
+    }
+
+    /// The keyword used to sort by this order in SQL
+    fn sql(self) -> &'static str {
+        match self {
+            Order::Ascending => "ASC",
+            Order::Descending => "DESC",
+        }
+    }
+
+    /// The comparison used to find the rows that come after a cursor in this order
+    fn comparison(self) -> &'static str {
+        match self {
+            Order::Ascending => ">",
+            Order::Descending => "<",
+        }
+    }
+}
+
+/// The fields that uploads can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, FromFormValue)]
+pub enum UploadSort {
+    /// When the upload was created
+    Timestamp,
+    /// The size of the upload
+    Size,
+    /// The filename of the upload
+    Name,
+}
+
+impl Default for UploadSort {
+    fn default() -> Self {
+        UploadSort::Timestamp
+    }
+}
+
+impl UploadSort {
+    /// The value of the sort as used in query strings
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            UploadSort::Timestamp => "timestamp",
+            UploadSort::Size => "size",
+            UploadSort::Name => "name",
+        }
+    }
+
+    /// The expression to sort the uploads table by
+    pub(super) fn key(self) -> &'static str {
+        match self {
+            UploadSort::Timestamp => "timestamp",
+            UploadSort::Size => "size",
+            UploadSort::Name => "lower(filename)",
+        }
+    }
+
+    /// If the sort key is a number rather than text
+    #[must_use]
+    pub fn numeric(self) -> bool {
+        self != UploadSort::Name
+    }
+}
+
+/// The fields that links can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, FromFormValue)]
+pub enum LinkSort {
+    /// When the link was created
+    Timestamp,
+    /// The amount of times the link was followed
+    Hits,
+    /// The uri that the link redirects to
+    #[form(value = "name")]
+    Uri,
+}
+
+impl Default for LinkSort {
+    fn default() -> Self {
+        LinkSort::Timestamp
+    }
+}
+
+impl LinkSort {
+    /// The value of the sort as used in query strings
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkSort::Timestamp => "timestamp",
+            LinkSort::Hits => "hits",
+            LinkSort::Uri => "name",
+        }
+    }
+
+    /// The expression to sort the links table by
+    pub(super) fn key(self) -> &'static str {
+        match self {
+            LinkSort::Timestamp => "timestamp",
+            LinkSort::Hits => "hits",
+            LinkSort::Uri => "lower(uri)",
+        }
+    }
+
+    /// If the sort key is a number rather than text
+    #[must_use]
+    pub fn numeric(self) -> bool {
+        self != LinkSort::Uri
+    }
+}
+
+/// The broad kinds of content that uploads can be filtered by
+#[derive(Debug, Clone, Copy, PartialEq, FromFormValue)]
+pub enum ContentKind {
+    /// Pictures and screenshots
+    Image,
+    /// Videos and screen recordings
+    Video,
+    /// Sounds and music
+    Audio,
+    /// Plain text, code and documents
+    Text,
+}
+
+impl ContentKind {
+    /// The value of the kind as used in query strings
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentKind::Image => "image",
+            ContentKind::Video => "video",
+            ContentKind::Audio => "audio",
+            ContentKind::Text => "text",
+        }
+    }
+
+    /// The patterns matching the content types that belong to this kind of content
+    fn content_types(self) -> &'static [&'static str] {
+        match self {
+            ContentKind::Image => &["image/%"],
+            ContentKind::Video => &["video/%"],
+            ContentKind::Audio => &["audio/%"],
+            ContentKind::Text => &[
+                "text/%",
+                "application/json%",
+                "application/javascript%",
+                "application/xml%",
+                "application/pdf%",
+            ],
+        }
+    }
+}
+
+/// A calendar day given in a query string as `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Day(pub NaiveDate);
+
+impl<'v> FromFormValue<'v> for Day {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        NaiveDate::parse_from_str(value.as_str(), "%Y-%m-%d")
+            .map(Day)
+            .map_err(|_| value)
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d"))
+    }
+}
+
+/// The filters that can be applied when listing uploads
+#[derive(Debug, Default)]
+pub struct UploadFilter {
+    /// Only show uploads with a filename containing this text
+    pub filename: Option<String>,
+    /// Only show uploads of this kind of content
+    pub kind: Option<ContentKind>,
+    /// Only show uploads created on or after this day
+    pub from: Option<Day>,
+    /// Only show uploads created on or before this day
+    pub to: Option<Day>,
+}
+
+impl UploadFilter {
+    /// Helper fn to check which kind of content is being filtered for
+    #[must_use]
+    pub fn is_kind(&self, kind: ContentKind) -> bool {
+        self.kind == Some(kind)
+    }
+
+    /// Build the conditions that rows must meet to pass the filter
+    pub(super) fn conditions(&self) -> Conditions {
+        let mut conditions = Conditions::default();
+
+        if let Some(filename) = &self.filename {
+            conditions.contains("filename", filename);
+        }
+
+        if let Some(kind) = self.kind {
+            let content_types = kind.content_types();
+
+            conditions.push(
+                format!(
+                    "({})",
+                    vec!["content_type LIKE ?"; content_types.len()].join(" OR ")
+                ),
+                content_types
+                    .iter()
+                    .map(|content_type| Value::Text((*content_type).to_owned())),
+            );
+        }
+
+        conditions.between("timestamp", self.from, self.to);
+
+        conditions
+    }
+}
+
+/// The filters that can be applied when listing links
+#[derive(Debug, Default)]
+pub struct LinkFilter {
+    /// Only show links with a uri containing this text
+    pub uri: Option<String>,
+    /// Only show links created on or after this day
+    pub from: Option<Day>,
+    /// Only show links created on or before this day
+    pub to: Option<Day>,
+}
+
+impl LinkFilter {
+    /// Build the conditions that rows must meet to pass the filter
+    pub(super) fn conditions(&self) -> Conditions {
+        let mut conditions = Conditions::default();
+
+        if let Some(uri) = &self.uri {
+            conditions.contains("uri", uri);
+        }
+
+        conditions.between("timestamp", self.from, self.to);
+
+        conditions
+    }
+}
+
+/// The conditions that rows in a listing must meet, along with their parameters
+#[derive(Debug, Default)]
+pub(super) struct Conditions {
+    clauses: Vec<String>,
+    params: Vec<Value>,
+}
+
+impl Conditions {
+    /// Add a condition along with the parameters it uses
+    fn push<S: Into<String>, P: IntoIterator<Item = Value>>(&mut self, clause: S, params: P) {
+        self.clauses.push(clause.into());
+        self.params.extend(params);
+    }
+
+    /// Require a column to contain some text, ignoring case
+    fn contains(&mut self, column: &str, text: &str) {
+        // Escape the wildcards so that they are matched literally
+        let pattern = text
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+
+        self.push(
+            format!("{} LIKE ? ESCAPE '\\'", column),
+            vec![Value::Text(format!("%{}%", pattern))],
+        );
+    }
+
+    /// Require a timestamp column to fall within a range of days, inclusive
+    fn between(&mut self, column: &str, from: Option<Day>, to: Option<Day>) {
+        if let Some(Day(from)) = from {
+            self.push(
+                format!("{} >= ?", column),
+                vec![Value::Integer(from.and_hms(0, 0, 0).timestamp())],
+            );
+        }
+
+        if let Some(Day(to)) = to {
+            self.push(
+                format!("{} < ?", column),
+                vec![Value::Integer(
+                    (to + Duration::days(1)).and_hms(0, 0, 0).timestamp(),
+                )],
+            );
+        }
+    }
+
+    /// The WHERE clause for the conditions, if there are any
+    fn clause(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+}
+
+/// A position in a sorted listing, made up of the sort key of a row
+/// and its id to break ties between rows with the same key
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    key: Value,
+    id: ID,
+}
+
+impl Cursor {
+    /// Parse a cursor from its text form, returning `None` if it is malformed
+    #[must_use]
+    pub fn parse(raw: &str, numeric: bool) -> Option<Cursor> {
+        // Ids never contain a `.` but text keys may, so split on the last one
+        let mut parts = raw.rsplitn(2, '.');
+        let id = ID::try_from(parts.next()?).ok()?;
+        let key = parts.next()?;
+
+        let key = if numeric {
+            Value::Integer(key.parse().ok()?)
+        } else {
+            Value::Text(key.to_owned())
+        };
+
+        Some(Cursor { key, id })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Value::Integer(key) => write!(f, "{}.{}", key, self.id),
+            Value::Text(key) => write!(f, "{}.{}", key, self.id),
+            _ => write!(f, ".{}", self.id),
+        }
+    }
+}
+
+/// Where in a listing to start a page
+#[derive(Debug)]
+pub enum Position {
+    /// The start of the listing
+    First,
+    /// The rows following a cursor
+    After(Cursor),
+    /// The rows preceding a cursor
+    Before(Cursor),
+}
+
+impl Position {
+    /// Get the position from the `after` and `before` cursors of a query string,
+    /// returning `None` if either cursor is malformed
+    #[must_use]
+    pub fn parse(after: Option<&str>, before: Option<&str>, numeric: bool) -> Option<Position> {
+        Some(match (after, before) {
+            (Some(after), _) => Position::After(Cursor::parse(after, numeric)?),
+            (None, Some(before)) => Position::Before(Cursor::parse(before, numeric)?),
+            (None, None) => Position::First,
+        })
+    }
+}
+
+/// A single page of a listing
+#[derive(Debug)]
+pub struct Page<T> {
+    /// The items on the page
+    pub items: Vec<T>,
+    /// The cursor to request the previous page with, if there is one
+    pub previous: Option<Cursor>,
+    /// The cursor to request the next page with, if there is one
+    pub next: Option<Cursor>,
+}
+
+/// A request for a page of rows from a table
+pub(super) struct PageQuery<'a> {
+    /// The table to list
+    pub table: &'a str,
+    /// The columns to select, which are passed to the row mapper in order
+    pub columns: &'a [&'a str],
+    /// The expression to sort the rows by
+    pub key: &'a str,
+    /// The direction to sort the rows in
+    pub order: Order,
+    /// The conditions that the rows must meet
+    pub conditions: Conditions,
+}
+
+impl<'a> PageQuery<'a> {
+    /// Fetch the page of rows at the given position
+    pub(super) fn fetch<T, F>(
+        self,
+        connection: &Connection,
+        position: Position,
+        map: F,
+    ) -> rusqlite::Result<Page<T>>
+    where
+        F: Fn(&Row) -> rusqlite::Result<T>,
+    {
+        let PageQuery {
+            table,
+            columns,
+            key,
+            order,
+            mut conditions,
+        } = self;
+
+        // Pages before a cursor are found by walking backwards from it
+        let (order, backwards) = match &position {
+            Position::Before(_) => (order.reverse(), true),
+            _ => (order, false),
+        };
+
+        if let Position::After(cursor) | Position::Before(cursor) = &position {
+            conditions.push(
+                format!("({}, id) {} (?, ?)", key, order.comparison()),
+                vec![cursor.key.clone(), Value::Blob(cursor.id.to_vec())],
+            );
+        }
+
+        // Fetch an extra row to find out if there are any more rows past this page
+        let sql = format!(
+            "SELECT {columns}, {key} AS page_key, id AS page_id FROM {table} {conditions}
+            ORDER BY {key} {order}, id {order} LIMIT {limit}",
+            columns = columns.join(", "),
+            key = key,
+            table = table,
+            conditions = conditions.clause(),
+            order = order.sql(),
+            limit = PAGE_SIZE + 1,
+        );
+        let params = conditions
+            .params
+            .iter()
+            .map(|param| param as &dyn ToSql)
+            .collect::<Vec<_>>();
+
+        let mut rows = connection
+            .prepare(&sql)?
+            .query_map::<rusqlite::Result<_>, _>(&params, |row| {
+                Ok((
+                    map(row)?,
+                    Cursor {
+                        key: row.get_checked("page_key")?,
+                        id: row.get_checked("page_id")?,
+                    },
+                ))
+            })?
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let more = rows.len() > PAGE_SIZE;
+        rows.truncate(PAGE_SIZE);
+
+        if backwards {
+            rows.reverse();
+        }
+
+        let (has_previous, has_next) = match position {
+            Position::First => (false, more),
+            Position::After(_) => (true, more),
+            Position::Before(_) => (more, true),
+        };
+        let previous = rows
+            .first()
+            .filter(|_| has_previous)
+            .map(|(_, cursor)| cursor.clone());
+        let next = rows
+            .last()
+            .filter(|_| has_next)
+            .map(|(_, cursor)| cursor.clone());
+
+        Ok(Page {
+            items: rows.into_iter().map(|(item, _)| item).collect(),
+            previous,
+            next,
+        })
+    }
+}
+
+/// Test parsing the cursors of listing pages
+#[test]
+fn test_cursor_parsing() {
+    let id = ID::new();
+
+    let cursor = Cursor::parse(&format!("1590000000.{}", id), true).unwrap();
+    assert_eq!(cursor.key, Value::Integer(1_590_000_000));
+    assert_eq!(cursor.to_string(), format!("1590000000.{}", id));
+
+    let cursor = Cursor::parse(&format!("screenshot.png.{}", id), false).unwrap();
+    assert_eq!(cursor.key, Value::Text("screenshot.png".into()));
+
+    assert!(Cursor::parse(&format!("big.{}", id), true).is_none());
+    assert!(Cursor::parse("1590000000", true).is_none());
+}
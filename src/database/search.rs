@@ -0,0 +1,38 @@
+//! Full text search over upload filenames and link targets
+
+/// The most results returned for each kind of resource in a search
+pub const SEARCH_LIMIT: u32 = 50;
+
+/// Convert text typed by a user into an FTS5 query, returning `None` if it contains nothing to search for
+///
+/// The text is split into words the same way that the index tokenizes filenames and uris,
+/// and each word is matched as a prefix so that partial names like `error_*.png` find
+/// `error_dialog.png`
+#[must_use]
+pub fn match_query(text: &str) -> Option<String> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/// Test turning typed text into full text search queries
+#[test]
+fn test_match_query() {
+    assert_eq!(
+        match_query("error_*.png"),
+        Some(r#""error"* "png"*"#.into())
+    );
+    assert_eq!(
+        match_query("grafana dashboard"),
+        Some(r#""grafana"* "dashboard"*"#.into())
+    );
+    assert_eq!(match_query(" *.\" "), None);
+}
@@ -16,3 +16,12 @@ pub fn generate_base64(chars: usize) -> String {
     // Encode the string
     base64::encode_config(data, base64::URL_SAFE)
 }
+
+/// Generate a random 256-bit key, encoded in standard base64 as rocket expects its secret key to be
+#[must_use]
+pub fn generate_secret_key() -> String {
+    let mut data = [0_u8; 32];
+    rand::thread_rng().fill_bytes(&mut data);
+
+    base64::encode(&data)
+}
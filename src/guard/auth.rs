@@ -1,20 +1,50 @@
 //! Tools for authenticating users and tokens
 
-use crate::{config::Config, user::User};
+use crate::{config::Config, database::Database, user::User};
+use chrono::Local;
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome, Request},
     State,
 };
-use std::convert::TryInto;
+use sha2::{Digest, Sha256};
+
+/// The name of the private cookie that holds the session token
+pub const SESSION_COOKIE: &str = "session";
+
+/// The amount of days that a session lasts for, matching the default expiry of private cookies
+pub const SESSION_DAYS: i64 = 7;
+
+/// Get the id that a session is stored under from its token, so that the token is never stored
+#[must_use]
+pub fn session_id(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
 
 /// A method of authentication
 #[derive(Debug)]
 pub enum Auth<'a> {
     /// A user account used for authentication
-    User(User),
-    /// An upload token used for authentication
-    UploadToken(&'a str),
+    User(&'a User),
+    /// An upload token belonging to a user used for authentication
+    UploadToken(&'a User),
+    /// The deprecated upload token shared between all users
+    SharedToken,
+}
+
+impl<'a> Auth<'a> {
+    /// Get the user that was authenticated, if any
+    pub fn user(&self) -> Option<&'a User> {
+        match self {
+            Auth::User(user) | Auth::UploadToken(user) => Some(*user),
+            Auth::SharedToken => None,
+        }
+    }
+
+    /// Get the username to record as the owner of any resources created
+    pub fn owner(&self) -> Option<String> {
+        self.user().map(|user| user.username.clone())
+    }
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for Auth<'a> {
@@ -22,54 +52,58 @@ impl<'a, 'r> FromRequest<'a, 'r> for Auth<'a> {
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
         // Load the config from the state
-        let config = request.guard::<State<Config>>().unwrap();
+        let config = request.guard::<State<Config>>().unwrap().inner();
 
         // Check for an upload token header
         if let Some(token) = request.headers().get_one("X-Upload-Token") {
-            // If the token matches that in the config, return success and auth type
-            if config.upload_token == token {
-                return Outcome::Success(Auth::UploadToken(token));
-            } else {
-                return Outcome::Failure((Status::Unauthorized, ()));
+            // If the token belongs to a user, return success with that user
+            if let Some(user) = config.token_user(token) {
+                return Outcome::Success(Auth::UploadToken(user));
+            }
+
+            // Fall back to the shared token if one is still configured
+            if config.is_shared_token(token) {
+                return Outcome::Success(Auth::SharedToken);
             }
+
+            return Outcome::Failure((Status::Unauthorized, ()));
         }
 
-        // If there was no token header, check the cookies
-        match request.cookies().get("auth") {
-            // Decode the base64 encoded cookie if it exists
-            Some(cookie) => match base64::decode_config(cookie.value(), base64::URL_SAFE) {
-                Ok(val) => {
-                    // Convert the raw bytes into a string
-                    let val = String::from_utf8_lossy(&val);
-
-                    // Split out the username and password from the cookie by a ":"
-                    let [username, password]: [&str; 2] =
-                        match val.split(':').take(2).collect::<Vec<_>>()[0..2].try_into() {
-                            Ok(v) => v,
-                            // Fail if it was unable to split it out
-                            Err(_) => return Outcome::Failure((Status::BadRequest, ())),
-                        };
-
-                    // Construct the user object
-                    let user = User {
-                        username: username.into(),
-                        password: password.into(),
-                    };
-
-                    // Check if any of the users in the config match
-                    if config.users.iter().any(|u| *u == user) {
-                        // Return the user in a success if so
-                        Outcome::Success(Auth::User(user))
-                    } else {
-                        // Fail if the user did not exist
-                        Outcome::Failure((Status::Unauthorized, ()))
-                    }
-                }
-                // Fail if malformed base64
-                Err(_) => Outcome::Failure((Status::BadRequest, ())),
-            },
+        // Sessions are never used on the user content domain, where uploaded pages could act as the user
+        if config.is_user_content_host(request) {
+            return Outcome::Forward(());
+        }
+
+        // If there was no token header, check for a session
+        let token = match request.cookies().get_private(SESSION_COOKIE) {
+            Some(cookie) => cookie.value().to_owned(),
             // Forward request if no header or cookie
-            None => Outcome::Forward(()),
+            None => return Outcome::Forward(()),
+        };
+
+        let database = match request.guard::<Database>() {
+            Outcome::Success(database) => database,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        match database
+            .sessions()
+            .get_session(&session_id(&token), Local::now().naive_local())
+        {
+            // Make sure that the user still exists
+            Ok(session) => match config.users.iter().find(|u| u.username == session.username) {
+                Some(user) => Outcome::Success(Auth::User(user)),
+                None => Outcome::Failure((Status::Unauthorized, ())),
+            },
+            // Fail if the session has expired or was revoked
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+            Err(e) => {
+                error!("Error fetching session: {}", e);
+
+                Outcome::Failure((Status::InternalServerError, ()))
+            }
         }
     }
 }
@@ -0,0 +1,179 @@
+//! Guards for retreving information about the client from the request
+
+use crate::{
+    config::Config,
+    database::{AccessRecord, ResourceKind},
+    id::ID,
+};
+use chrono::Local;
+use derive_more::{AsRef, Deref};
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request, State,
+};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Guard for retreving the ip address of the client, taking the `X-Real-IP`
+/// header set by a reverse proxy into account
+#[derive(Debug, AsRef, Deref, Clone, Copy)]
+pub struct ClientIp(Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientIp(request.client_ip()))
+    }
+}
+
+/// Guard for checking if a request was made to the domain that uploads with active content are served from
+#[derive(Debug, AsRef, Deref, Clone, Copy)]
+pub struct UserContentHost(bool);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserContentHost {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap().inner();
+
+        Outcome::Success(UserContentHost(config.is_user_content_host(request)))
+    }
+}
+
+/// Parts of the user agents of the bots that fetch links pasted into chat apps and social media to embed them
+const CRAWLERS: &[&str] = &[
+    "discordbot",
+    "slackbot",
+    "twitterbot",
+    "facebookexternalhit",
+    "telegrambot",
+    "whatsapp",
+    "linkedinbot",
+    "skypeuripreview",
+];
+
+/// The query parameter that asks for the contents of an upload even when the client is a crawler,
+/// as crawlers fetch the media of preview pages with the same user agent
+pub const RAW_QUERY: &str = "raw";
+
+/// Check if a user agent belongs to a bot that fetches links to embed them
+#[must_use]
+pub fn is_crawler(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+
+    CRAWLERS.iter().any(|crawler| user_agent.contains(crawler))
+}
+
+/// Guard for checking if a request was made by a bot fetching a link to embed it,
+/// which gets a preview page with the metadata for the embed instead of the upload itself
+#[derive(Debug, AsRef, Deref, Clone, Copy)]
+pub struct Crawler(bool);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Crawler {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let raw = request
+            .raw_query_items()
+            .map_or(false, |mut items| items.any(|item| item.key == RAW_QUERY));
+
+        Outcome::Success(Crawler(
+            !raw && request
+                .headers()
+                .get_one("User-Agent")
+                .map_or(false, is_crawler),
+        ))
+    }
+}
+
+/// Guard for retreving the user agent of the client, if it sent one
+#[derive(Debug, AsRef, Deref)]
+pub struct UserAgent(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(UserAgent(
+            request.headers().get_one("User-Agent").map(String::from),
+        ))
+    }
+}
+
+/// Guard for retreving the details of a visitor to record in the access log,
+/// which forwards if the access log is disabled
+#[derive(Debug)]
+pub struct Visitor {
+    /// The page that linked the visitor here
+    pub referrer: Option<String>,
+    /// The user agent of the visitor
+    pub user_agent: Option<String>,
+    /// The hash of the visitor's ip address, salted with the secret key so that it can not be
+    /// reversed. Left out if there is no secret key to salt it with
+    pub ip_hash: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Visitor {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap().inner();
+
+        if config.access_log_days == 0 {
+            return Outcome::Forward(());
+        }
+
+        let headers = request.headers();
+        let ip_hash = match (&config.secret_key, request.client_ip()) {
+            (Some(secret_key), Some(ip)) => Some(format!(
+                "{:x}",
+                Sha256::new()
+                    .chain(secret_key)
+                    .chain(ip.to_string())
+                    .result()
+            )),
+            _ => None,
+        };
+
+        Outcome::Success(Visitor {
+            referrer: headers.get_one("Referer").map(String::from),
+            user_agent: headers.get_one("User-Agent").map(String::from),
+            ip_hash,
+        })
+    }
+}
+
+impl Visitor {
+    /// Create the record of this visitor accessing an upload or link
+    #[must_use]
+    pub fn access(self, kind: ResourceKind, resource: &ID) -> AccessRecord {
+        AccessRecord {
+            resource: resource.clone(),
+            kind,
+            timestamp: Local::now().naive_local(),
+            referrer: self.referrer,
+            user_agent: self.user_agent,
+            ip_hash: self.ip_hash,
+        }
+    }
+}
+
+/// Test matching the user agents of bots that embed links
+#[test]
+fn test_is_crawler() {
+    assert!(is_crawler(
+        "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)"
+    ));
+    assert!(is_crawler(
+        "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)"
+    ));
+    assert!(is_crawler("Twitterbot/1.0"));
+    assert!(is_crawler(
+        "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)"
+    ));
+    assert!(!is_crawler(
+        "Mozilla/5.0 (X11; Linux x86_64; rv:81.0) Gecko/20100101 Firefox/81.0"
+    ));
+    assert!(!is_crawler("ShareX/13.0.0"));
+}
@@ -0,0 +1,46 @@
+//! Guard for retreving the headers that decide how much of an upload is sent
+
+use rocket::{
+    http::Method,
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+/// The headers of a request that make it conditional or ask for only part of the contents
+#[derive(Debug, Default, Clone)]
+pub struct Conditions {
+    /// Whether only the headers of the response were asked for
+    pub head: bool,
+    /// The `Range` header
+    pub range: Option<String>,
+    /// The `If-Range` header
+    pub if_range: Option<String>,
+    /// The `If-None-Match` header
+    pub if_none_match: Option<String>,
+    /// The `If-Modified-Since` header
+    pub if_modified_since: Option<String>,
+}
+
+impl Conditions {
+    /// Read the conditions from the headers of a request
+    #[must_use]
+    pub fn of(request: &Request) -> Self {
+        let header = |name| request.headers().get_one(name).map(str::to_owned);
+
+        Conditions {
+            head: request.method() == Method::Head,
+            range: header("Range"),
+            if_range: header("If-Range"),
+            if_none_match: header("If-None-Match"),
+            if_modified_since: header("If-Modified-Since"),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Conditions {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Conditions::of(request))
+    }
+}
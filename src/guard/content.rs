@@ -2,21 +2,31 @@
 
 use derive_more::{AsRef, Deref};
 use rocket::{
+    http::Status,
     request::{FromRequest, Outcome},
     Request,
 };
+use std::num::ParseIntError;
 
-/// Guard for retreving the content length from a request
+/// Guard for retreving the content length from a request, if it was sent.
+/// Fails with a 400 if the header is not a valid length
 #[derive(Debug, AsRef, Deref)]
-pub struct ContentLength(usize);
+pub struct ContentLength(Option<u64>);
 
 impl<'a, 'r> FromRequest<'a, 'r> for ContentLength {
-    type Error = !;
+    type Error = ParseIntError;
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
         match request.headers().get_one("Content-Length") {
-            Some(length) => Outcome::Success(ContentLength(length.parse().unwrap())),
-            _ => Outcome::Forward(()),
+            Some(length) => match length.trim().parse() {
+                Ok(length) => Outcome::Success(ContentLength(Some(length))),
+                Err(e) => {
+                    warn!("Invalid Content-Length header: {:?}", length);
+
+                    Outcome::Failure((Status::BadRequest, e))
+                }
+            },
+            None => Outcome::Success(ContentLength(None)),
         }
     }
 }
@@ -0,0 +1,94 @@
+//! Guard for retreving how long a new upload or link should last
+
+use chrono::Duration;
+use derive_more::{AsRef, Deref};
+use rocket::{
+    http::{RawStr, Status},
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+/// The longest that an upload or link can be set to last, one hundred years
+const MAX_LIFETIME: i64 = 100 * 365 * 24 * 60 * 60;
+
+/// Guard for retreving how long a new upload or link should last from the `X-Expires` header,
+/// failing with a 400 if the header is malformed
+#[derive(Debug, AsRef, Deref, Clone, Copy)]
+pub struct Expiry(Option<Duration>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Expiry {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Expires") {
+            None => Outcome::Success(Expiry(None)),
+            Some(lifetime) => match parse_lifetime(lifetime) {
+                Some(lifetime) => Outcome::Success(Expiry(Some(lifetime))),
+                None => {
+                    warn!("Invalid X-Expires header: {}", lifetime);
+
+                    Outcome::Failure((Status::BadRequest, ()))
+                }
+            },
+        }
+    }
+}
+
+impl Expiry {
+    /// Get how long the new upload or link should last, preferring the `expires`
+    /// query parameter over the header if both were sent
+    ///
+    /// # Errors
+    /// - If the query parameter is malformed, with a 400 status
+    pub fn lifetime(self, query: Option<&RawStr>) -> Result<Option<Duration>, Status> {
+        match query {
+            None => Ok(self.0),
+            Some(lifetime) => match parse_lifetime(&lifetime.percent_decode_lossy()) {
+                Some(lifetime) => Ok(Some(lifetime)),
+                None => {
+                    warn!("Invalid expires query parameter: {}", lifetime);
+
+                    Err(Status::BadRequest)
+                }
+            },
+        }
+    }
+}
+
+/// Parse a lifetime such as `90`, `30m`, `12h`, `7d` or `2w`, where plain numbers are seconds
+#[must_use]
+pub fn parse_lifetime(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (amount, unit) = match text.char_indices().last()? {
+        (index, 's') => (&text[..index], 1),
+        (index, 'm') => (&text[..index], 60),
+        (index, 'h') => (&text[..index], 60 * 60),
+        (index, 'd') => (&text[..index], 24 * 60 * 60),
+        (index, 'w') => (&text[..index], 7 * 24 * 60 * 60),
+        _ => (text, 1),
+    };
+
+    let seconds = amount.parse::<i64>().ok()?.checked_mul(unit)?;
+
+    if seconds > 0 && seconds <= MAX_LIFETIME {
+        Some(Duration::seconds(seconds))
+    } else {
+        None
+    }
+}
+
+/// Test parsing how long uploads and links should last
+#[test]
+fn test_lifetime_parsing() {
+    assert_eq!(parse_lifetime("90"), Some(Duration::seconds(90)));
+    assert_eq!(parse_lifetime("30m"), Some(Duration::minutes(30)));
+    assert_eq!(parse_lifetime("12h"), Some(Duration::hours(12)));
+    assert_eq!(parse_lifetime("7d"), Some(Duration::days(7)));
+    assert_eq!(parse_lifetime("2w"), Some(Duration::weeks(2)));
+
+    assert_eq!(parse_lifetime("0"), None);
+    assert_eq!(parse_lifetime("-5m"), None);
+    assert_eq!(parse_lifetime("h"), None);
+    assert_eq!(parse_lifetime("5y"), None);
+    assert_eq!(parse_lifetime("9999999999999w"), None);
+}
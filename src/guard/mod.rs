@@ -1,4 +1,9 @@
 //! Rockets guards
 
 pub mod auth;
+pub mod client;
+pub mod conditions;
 pub mod content;
+pub mod expiry;
+pub mod signature;
+pub mod unlock;
@@ -0,0 +1,102 @@
+//! Guard for checking the signatures of time-limited urls to uploads
+
+use crate::{config::Config, id::ID, unix_timestamp};
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac};
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request, State,
+};
+use sha2::Sha256;
+
+/// The HMAC used to sign urls
+type UrlMac = Hmac<Sha256>;
+
+/// Create the HMAC of an upload's url that expires at the given unix timestamp
+fn url_mac(key: &str, id: &ID, filename: &str, expires: i64) -> UrlMac {
+    // HMAC accepts keys of any length, so this can not fail
+    let mut mac = UrlMac::new_varkey(key.as_bytes()).expect("HMAC rejected the key");
+    mac.input(format!("{}/{}:{}", id, filename, expires).as_bytes());
+
+    mac
+}
+
+/// Sign the url of an upload so that it can be viewed until the given unix timestamp
+#[must_use]
+pub fn sign(key: &str, id: &ID, filename: &str, expires: i64) -> String {
+    base64::encode_config(
+        url_mac(key, id, filename, expires).result().code(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Check if a signature was made for the url of an upload that expires at the given unix timestamp
+#[must_use]
+pub fn verify(key: &str, id: &ID, filename: &str, expires: i64, signature: &str) -> bool {
+    match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+        Ok(signature) => url_mac(key, id, filename, expires)
+            .verify(&signature)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Guard for retreving the signature of a time-limited url from its `exp` and `sig` query parameters,
+/// which forwards if either is missing
+#[derive(Debug)]
+pub struct Signature<'a> {
+    /// The key that urls are signed with, if one is configured
+    key: Option<&'a str>,
+    /// The unix timestamp of when the url stops working
+    expires: i64,
+    /// The signature of the url
+    signature: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Signature<'a> {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap().inner();
+
+        match (
+            request.get_query_value("exp"),
+            request.get_query_value("sig"),
+        ) {
+            (Some(Ok(expires)), Some(Ok(signature))) => Outcome::Success(Signature {
+                key: config.signing_key.as_deref(),
+                expires,
+                signature,
+            }),
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+impl<'a> Signature<'a> {
+    /// Check if the signature is valid for the url of an upload and has not expired yet
+    #[must_use]
+    pub fn allows(&self, id: &ID, filename: &str, now: NaiveDateTime) -> bool {
+        match self.key {
+            Some(key) => {
+                self.expires > unix_timestamp(now)
+                    && verify(key, id, filename, self.expires, &self.signature)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Test that signatures only match the url they were made for
+#[test]
+fn test_url_signing() {
+    let id = ID::new();
+    let signature = sign("key", &id, "cat.png", 1000);
+
+    assert!(verify("key", &id, "cat.png", 1000, &signature));
+    assert!(!verify("key", &id, "cat.png", 1001, &signature));
+    assert!(!verify("key", &id, "dog.png", 1000, &signature));
+    assert!(!verify("key", &ID::new(), "cat.png", 1000, &signature));
+    assert!(!verify("other", &id, "cat.png", 1000, &signature));
+    assert!(!verify("key", &id, "cat.png", 1000, "not a signature"));
+}
@@ -0,0 +1,133 @@
+//! Guards for unlocking password protected uploads and links
+
+use crate::{
+    config::Config,
+    id::ID,
+    limiter::LoginLimiter,
+    user::{constant_time_eq, Password},
+};
+use rocket::{
+    http::{Cookie, Cookies, SameSite},
+    request::{FromRequest, Outcome},
+    Request, State,
+};
+use sha2::{Digest, Sha256};
+use std::{net::IpAddr, time::Duration};
+
+/// The name of the private cookies that show a visitor has unlocked an upload or link.
+/// Each one is scoped to the path of what it unlocked, so that the browser only sends the matching one
+pub const UNLOCK_COOKIE: &str = "unlock";
+
+/// Get the value of the cookie that unlocks an upload or link, which is tied to its password hash
+/// so that changing the password locks it again
+#[must_use]
+pub fn unlock_token(id: &ID, password: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::new().chain(id.to_string()).chain(password).result()
+    )
+}
+
+/// Guard for retreving the unlock cookie that was sent with a request
+#[derive(Debug)]
+pub struct Unlock(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Unlock {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Unlock(
+            request
+                .cookies()
+                .get_private(UNLOCK_COOKIE)
+                .map(|cookie| cookie.value().to_owned()),
+        ))
+    }
+}
+
+impl Unlock {
+    /// Check if an upload or link can be accessed, either because it has no password
+    /// or because the visitor has unlocked it
+    #[must_use]
+    pub fn allows(&self, id: &ID, password: Option<&str>) -> bool {
+        match (password, &self.0) {
+            (None, _) => true,
+            (Some(password), Some(token)) => constant_time_eq(token, &unlock_token(id, password)),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// The outcome of trying to unlock an upload or link with a password
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attempt {
+    /// The password matched and the unlock cookie was added
+    Unlocked,
+    /// The password did not match
+    Incorrect,
+    /// There have been too many wrong passwords, with how long until it can be tried again
+    Limited(Duration),
+}
+
+/// Guard for unlocking an upload or link, where wrong passwords are limited the same way as failed logins
+pub struct Unlocker<'a> {
+    /// The cookies to add the unlock cookie to
+    cookies: Cookies<'a>,
+    /// The limiter of failed attempts
+    limiter: &'a LoginLimiter,
+    /// The ip address of the client
+    ip: Option<IpAddr>,
+    /// Whether the unlock cookie should only be sent over https
+    https: bool,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Unlocker<'a> {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap().inner();
+        let limiter = request.guard::<State<LoginLimiter>>().unwrap().inner();
+
+        Outcome::Success(Unlocker {
+            cookies: request.cookies(),
+            limiter,
+            ip: request.client_ip(),
+            https: config.https,
+        })
+    }
+}
+
+impl<'a> Unlocker<'a> {
+    /// Try to unlock the upload or link with the given id and password hash, adding a cookie
+    /// scoped to its path if the entered password matches
+    pub fn attempt(&mut self, id: &ID, password: &str, entered: &str, path: String) -> Attempt {
+        let key = id.to_string();
+
+        if let Some(retry_after) = self.limiter.check(self.ip, &key) {
+            info!("Blocked unlock attempt: ID: {} IP: {:?}", id, self.ip);
+
+            return Attempt::Limited(retry_after);
+        }
+
+        if Password::Hashed(password.to_owned()).verify(entered) {
+            self.limiter.succeed(self.ip, &key);
+            self.cookies.add_private(
+                Cookie::build(UNLOCK_COOKIE, unlock_token(id, password))
+                    .path(path)
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .secure(self.https)
+                    .finish(),
+            );
+
+            Attempt::Unlocked
+        } else {
+            warn!("Failed unlock attempt: ID: {} IP: {:?}", id, self.ip);
+
+            match self.limiter.fail(self.ip, &key) {
+                Some(retry_after) => Attempt::Limited(retry_after),
+                None => Attempt::Incorrect,
+            }
+        }
+    }
+}
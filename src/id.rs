@@ -17,7 +17,7 @@ use std::{convert::TryInto, fmt, ops::Deref};
 use uuid::Uuid;
 
 /// An identifier for a unit in the system
-#[derive(Debug, Default, Deref, AsRef, Display)]
+#[derive(Debug, Clone, Default, Deref, AsRef, Display)]
 #[as_ref(forward)]
 #[display(fmt = "{}", "String::from(self)")]
 pub struct ID([u8; 16]);
@@ -8,18 +8,27 @@ extern crate rocket;
 #[macro_use]
 extern crate log;
 
+use chrono::{Local, NaiveDateTime, TimeZone};
 use std::{fs, io, path::Path};
 
 pub mod args;
 pub mod config;
+pub mod csp;
 pub mod database;
 pub mod generate;
 pub mod guard;
 pub mod id;
+pub mod limiter;
+pub mod reaper;
 pub mod responder;
 pub mod routes;
+pub mod sniff;
+pub mod storage;
+pub mod stream;
 pub mod templates;
+pub mod thumbnail;
 pub mod user;
+pub mod viewer;
 
 /// Helper function to create the parent directories of a file
 pub fn create_parent_directories<P: AsRef<Path>>(path: &P) -> io::Result<bool> {
@@ -36,3 +45,12 @@ pub fn create_parent_directories<P: AsRef<Path>>(path: &P) -> io::Result<bool> {
 
     Ok(false)
 }
+
+/// Helper function to convert a local time, which is how times are kept, into a unix timestamp
+#[must_use]
+pub fn unix_timestamp(local: NaiveDateTime) -> i64 {
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map_or_else(|| local.timestamp(), |time| time.timestamp())
+}
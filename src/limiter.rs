@@ -0,0 +1,132 @@
+//! Limiting of repeated failed login attempts
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The amount of failed attempts allowed before further attempts are locked out
+const MAX_FAILURES: u32 = 5;
+
+/// How long failed attempts are remembered for, which is also how long a lockout lasts
+const WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// What failed login attempts are counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// Attempts coming from a client's ip address
+    Ip(IpAddr),
+    /// Attempts to log into a user
+    Username(String),
+}
+
+/// The failed login attempts counted against a key
+#[derive(Debug)]
+struct Attempts {
+    /// The amount of attempts that have failed
+    failures: u32,
+    /// When the latest attempt failed
+    last_failure: Instant,
+}
+
+/// An in-memory limiter of failed login attempts, keyed by both the client's ip address and the username
+#[derive(Debug, Default)]
+pub struct LoginLimiter {
+    /// The failed attempts for every key
+    attempts: Mutex<HashMap<Key, Attempts>>,
+}
+
+impl LoginLimiter {
+    /// Create a new limiter with no failed attempts
+    #[must_use]
+    pub fn new() -> LoginLimiter {
+        LoginLimiter::default()
+    }
+
+    /// Get the keys that a login attempt is counted against
+    fn keys(ip: Option<IpAddr>, username: &str) -> Vec<Key> {
+        let mut keys = vec![Key::Username(username.to_owned())];
+        keys.extend(ip.map(Key::Ip));
+
+        keys
+    }
+
+    /// Check if a login attempt is locked out, returning how long until it can be tried again
+    pub fn check(&self, ip: Option<IpAddr>, username: &str) -> Option<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+
+        Self::keys(ip, username)
+            .iter()
+            .filter_map(|key| attempts.get(key))
+            .filter(|attempts| attempts.failures >= MAX_FAILURES)
+            .filter_map(|attempts| WINDOW.checked_sub(attempts.last_failure.elapsed()))
+            .max()
+    }
+
+    /// Record a failed login attempt, returning how long it is locked out for if this failure caused a lockout
+    pub fn fail(&self, ip: Option<IpAddr>, username: &str) -> Option<Duration> {
+        let mut attempts = self.attempts.lock().unwrap();
+
+        // Forget any attempts that have expired
+        attempts.retain(|_, attempts| attempts.last_failure.elapsed() < WINDOW);
+
+        let mut locked = false;
+        for key in Self::keys(ip, username) {
+            let attempts = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: Instant::now(),
+            });
+            attempts.failures += 1;
+            attempts.last_failure = Instant::now();
+
+            if attempts.failures == MAX_FAILURES {
+                warn!(
+                    "Locking out login attempts for {:?} for {} seconds after {} failures",
+                    key,
+                    WINDOW.as_secs(),
+                    attempts.failures
+                );
+
+                locked = true;
+            }
+        }
+
+        if locked {
+            Some(WINDOW)
+        } else {
+            None
+        }
+    }
+
+    /// Forget the failed attempts of a successful login
+    pub fn succeed(&self, ip: Option<IpAddr>, username: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+
+        for key in Self::keys(ip, username) {
+            attempts.remove(&key);
+        }
+    }
+}
+
+/// Test that repeated failures lock out further attempts
+#[test]
+fn test_login_lockout() {
+    let limiter = LoginLimiter::new();
+    let ip = Some("127.0.0.1".parse().unwrap());
+
+    for _ in 1..MAX_FAILURES {
+        assert_eq!(limiter.fail(ip, "admin"), None);
+    }
+    assert_eq!(limiter.check(ip, "admin"), None);
+
+    assert_eq!(limiter.fail(ip, "admin"), Some(WINDOW));
+    assert!(limiter.check(ip, "admin").is_some());
+    assert!(limiter.check(None, "admin").is_some());
+    assert!(limiter.check(ip, "someone").is_some());
+    assert!(limiter.check(None, "someone").is_none());
+
+    limiter.succeed(ip, "admin");
+    assert_eq!(limiter.check(ip, "admin"), None);
+}
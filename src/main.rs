@@ -6,26 +6,35 @@ use io::{Error, ErrorKind};
 use rocket::{
     config::{Environment, Value},
     fairing::AdHoc,
-    http::Header,
+    http::{ContentType, Header},
     State,
 };
 use rocket_contrib::{helmet::SpaceHelmet, serve::StaticFiles};
+use rusqlite::Connection;
 use simplelog::{
     CombinedLogger, ConfigBuilder as LogConfigBuilder, LevelFilter, SharedLogger, SimpleLogger,
     TermLogger, TerminalMode,
 };
-use std::{collections::HashMap, io};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 use structopt::StructOpt;
-use sxfs::args::Args;
+use sxfs::args::{Args, Command};
 use sxfs::config::Config;
 use sxfs::routes;
 use sxfs::{
-    create_parent_directories,
-    database::Database,
+    create_parent_directories, csp,
+    database::{migrations, Database},
+    limiter::LoginLimiter,
+    reaper,
+    storage::{filesystem::FileStorage, Storage},
     templates::{
         uploader::{ShortenerTemplate, UploaderTemplate},
         UpdatableTemplate,
     },
+    user::hash_password,
+    viewer::Highlighter,
 };
 
 #[macro_use]
@@ -59,6 +68,13 @@ fn main() -> io::Result<()> {
     ])
     .ok();
 
+    // Run the subcommand instead of the server if one was given
+    match &args.command {
+        Some(Command::Migrate { dry_run }) => return migrate(&args, *dry_run),
+        Some(Command::HashPassword { password }) => return print_password_hash(password.clone()),
+        None => {}
+    }
+
     // Load config
     debug!("{}", "Loading Config...".yellow());
     let config: Config = match Config::load(&args.config) {
@@ -71,25 +87,38 @@ fn main() -> io::Result<()> {
         Ok(config) => config,
     };
 
-    // Write out uploaders
-    match UploaderTemplate::new(&config).update(&args.uploaders.join("uploader.sxcu")) {
-        Ok(()) => {}
-        Err(e) => {
-            error!("{} {}", "Failed to write uploader template:".red(), e);
-            panic!("{:?}", e);
+    // Write out uploaders for each user with their own token
+    for user in config.users.iter() {
+        let (token, uploaders) = match user.tokens.first() {
+            Some(token) => (token, args.uploaders.join(&user.username)),
+            None => {
+                warn!("User {} has no upload tokens", user.username);
+                continue;
+            }
+        };
+
+        match UploaderTemplate::new(&config, token).update(&uploaders.join("uploader.sxcu")) {
+            Ok(()) => {}
+            Err(e) => {
+                error!("{} {}", "Failed to write uploader template:".red(), e);
+                panic!("{:?}", e);
+            }
         }
-    }
-    match ShortenerTemplate::new(&config).update(&args.uploaders.join("shortener.sxcu")) {
-        Ok(()) => {}
-        Err(e) => {
-            error!("{} {}", "Failed to write shortener template:".red(), e);
-            panic!("{:?}", e);
+        match ShortenerTemplate::new(&config, token).update(&uploaders.join("shortener.sxcu")) {
+            Ok(()) => {}
+            Err(e) => {
+                error!("{} {}", "Failed to write shortener template:".red(), e);
+                panic!("{:?}", e);
+            }
         }
     }
 
     // Create parent directories for database
     create_parent_directories(&args.database)?;
 
+    // Open the storage backend for the contents of uploads
+    let storage: Box<dyn Storage> = Box::new(FileStorage::new(&args.storage)?);
+
     // Configure contrib database for rocket
     let mut database_config = HashMap::new();
     let mut databases = HashMap::new();
@@ -98,18 +127,30 @@ fn main() -> io::Result<()> {
     databases.insert("db", Value::from(database_config));
 
     // Configure web interface
-    let rocket_config = rocket::Config::build(
+    let mut rocket_config = rocket::Config::build(
         Environment::active().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
     )
     .address(args.address.to_string())
     .port(args.port)
-    .extra("databases", databases)
-    .finalize()
-    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    .extra("databases", databases);
+
+    // Keep session cookies valid across restarts
+    if let Some(secret_key) = &config.secret_key {
+        rocket_config = rocket_config.secret_key(secret_key.as_str());
+    }
+
+    let rocket_config = rocket_config
+        .finalize()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    // Keep how long accesses are logged for, as the config is moved into rocket
+    let access_log_days = config.access_log_days;
+    let ui_policy = csp::ui_policy(&config);
 
     // Start web interface
     let rocket = rocket::custom(rocket_config)
         .register(catchers![
+            routes::catcher::gone,
             routes::catcher::internal_error,
             routes::catcher::not_found,
             routes::catcher::unauthorized,
@@ -120,24 +161,69 @@ fn main() -> io::Result<()> {
                 routes::auth::login_form,
                 routes::auth::login_submit,
                 routes::auth::logout,
+                routes::auth::revoke_session,
+                routes::auth::sessions,
+                routes::highlight_css,
                 routes::index,
                 routes::link::all,
                 routes::link::create,
                 routes::link::delete,
                 routes::link::follow,
+                routes::link::protect_submit,
+                routes::link::protect,
+                routes::link::unlock,
                 routes::public_files,
+                routes::search::search_json,
+                routes::search::search,
+                routes::stats::stats,
                 routes::upload_url,
                 routes::upload::all,
                 routes::upload::create,
                 routes::upload::delete_by_id,
                 routes::upload::delete,
+                routes::upload::preview,
+                routes::upload::protect_submit,
+                routes::upload::protect,
+                routes::upload::set_visibility,
+                routes::upload::thumbnail,
+                routes::upload::unlock,
                 routes::upload::view_by_id,
                 routes::upload::view,
                 routes::uploaders,
             ],
         )
+        .mount(
+            "/api/v1",
+            routes![
+                routes::api::delete_link,
+                routes::api::delete_upload,
+                routes::api::link,
+                routes::api::links,
+                routes::api::sign_upload,
+                routes::api::stats,
+                routes::api::upload,
+                routes::api::uploads,
+            ],
+        )
         .manage(config)
+        .manage(storage)
+        .manage(LoginLimiter::new())
+        .manage(Highlighter::new())
         .attach(SpaceHelmet::default())
+        .attach(AdHoc::on_response(
+            "Content-Security-Policy",
+            move |req, res| {
+                let config = req.guard::<State<Config>>().unwrap();
+
+                // Uploads set their own policy, and only uploads are served on the user content domain
+                if res.content_type() == Some(ContentType::HTML)
+                    && !res.headers().contains("Content-Security-Policy")
+                    && !config.is_user_content_host(req)
+                {
+                    res.set_header(Header::new("Content-Security-Policy", ui_policy.clone()));
+                }
+            },
+        ))
         .attach(AdHoc::on_response("No-Cache", |req, res| {
             if let [first_path, ..] = req.uri().segments().collect::<Vec<_>>().as_slice() {
                 // Ignore uploads directory
@@ -164,10 +250,26 @@ fn main() -> io::Result<()> {
             res.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
             res.set_header(Header::new(
                 "Access-Control-Allow-Methods",
-                "POST, GET, OPTIONS",
+                "POST, GET, DELETE, OPTIONS",
             ));
         }))
-        .attach(Database::fairing());
+        .attach(Database::fairing())
+        .attach(migrations::fairing());
+
+    // Remove expired uploads, links, sessions and old accesses in the background
+    match Database::get_one(&rocket) {
+        Some(database) => {
+            reaper::spawn(
+                database,
+                Box::new(FileStorage::new(&args.storage)?),
+                access_log_days,
+            )?;
+        }
+        None => error!(
+            "{}",
+            "Failed to connect to the database to remove expired items".red()
+        ),
+    }
 
     if cfg!(debug_assertions) {
         rocket.mount("/src", StaticFiles::from("src"))
@@ -179,6 +281,67 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+/// Apply or list the pending database migrations without starting the server
+fn migrate(args: &Args, dry_run: bool) -> io::Result<()> {
+    create_parent_directories(&args.database)?;
+
+    let connection =
+        Connection::open(&args.database).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    let version =
+        migrations::schema_version(&connection).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    let pending = migrations::pending(&connection).map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+    if pending.is_empty() {
+        println!("Database is up to date at schema version {}", version);
+
+        return Ok(());
+    }
+
+    if dry_run {
+        println!(
+            "Database is at schema version {} with {} pending migrations:",
+            version,
+            pending.len()
+        );
+        for (version, migration) in pending {
+            println!("  {}: {}", version, migration.description);
+        }
+
+        return Ok(());
+    }
+
+    let storage = FileStorage::new(&args.storage)?;
+    for (version, migration) in &pending {
+        println!("Applying migration {}: {}", version, migration.description);
+    }
+    migrations::migrate(&connection, &storage).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    println!("{}", "Database migrated successfully".green());
+
+    Ok(())
+}
+
+/// Print the hash of a password to be put into the config
+fn print_password_hash(password: Option<String>) -> io::Result<()> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            eprint!("Password: ");
+            io::stderr().flush()?;
+
+            let mut password = String::new();
+            io::stdin().read_line(&mut password)?;
+            password.trim_end_matches(&['\r', '\n'][..]).to_owned()
+        }
+    };
+
+    println!(
+        "{}",
+        hash_password(&password).map_err(|e| Error::new(ErrorKind::Other, e))?
+    );
+
+    Ok(())
+}
+
 /// Create a configured logger with the specified settings
 fn create_logger(filters: &'static [&'static str], level: LevelFilter) -> Box<dyn SharedLogger> {
     let mut config = LogConfigBuilder::new();
@@ -0,0 +1,64 @@
+//! Background thread that removes expired uploads, links, sessions and old accesses
+
+use crate::{database::Database, storage::Storage};
+use chrono::Local;
+use std::{
+    io,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How often the reaper looks for anything that has expired
+pub const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Start the reaper on a background thread, which runs for as long as the server does.
+/// Accesses are kept in the access log for the given amount of days
+///
+/// # Errors
+/// - If the thread could not be spawned
+pub fn spawn(
+    database: Database,
+    storage: Box<dyn Storage>,
+    access_log_days: u32,
+) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("reaper".into())
+        .spawn(move || loop {
+            reap(&database, storage.as_ref(), access_log_days);
+            thread::sleep(INTERVAL);
+        })
+}
+
+/// Delete all of the uploads, links and sessions that have expired,
+/// along with the accesses that are older than the access log is kept for
+pub fn reap(database: &Database, storage: &dyn Storage, access_log_days: u32) {
+    let now = Local::now().naive_local();
+
+    match database.uploads().delete_expired(now) {
+        Err(e) => error!("Error deleting expired uploads: {}", e),
+        Ok(blobs) => {
+            for blob in blobs {
+                if let Err(e) = storage.delete(&blob) {
+                    error!("Error deleting blob: Blob: {} Error: {}", blob, e);
+                }
+            }
+        }
+    }
+
+    match database.links().delete_expired(now) {
+        Err(e) => error!("Error deleting expired links: {}", e),
+        Ok(0) => {}
+        Ok(deleted) => info!("Deleted {} expired links", deleted),
+    }
+
+    if let Err(e) = database.sessions().delete_expired(now) {
+        error!("Error deleting expired sessions: {}", e);
+    }
+
+    let cutoff = now - chrono::Duration::days(access_log_days.into());
+    match database.accesses().delete_before(cutoff) {
+        Err(e) => error!("Error deleting old accesses: {}", e),
+        Ok(0) => {}
+        Ok(deleted) => debug!("Deleted {} old accesses", deleted),
+    }
+}
@@ -0,0 +1,270 @@
+//! A responder that streams a stored blob, supporting range requests and conditional gets
+
+use crate::{guard::conditions::Conditions, storage::BlobReader, unix_timestamp};
+use chrono::NaiveDateTime;
+use rocket::{
+    http::{uri::Uri, ContentType, Status},
+    response::{self, Body, Responder, Response},
+    Request,
+};
+use std::io::{Read, Seek, SeekFrom};
+
+/// The format of dates in http headers
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Convert the local time that a blob was last modified at into UTC, to the second, as http dates are given in
+fn http_time(local: NaiveDateTime) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(unix_timestamp(local), 0)
+}
+
+/// A response that streams the contents of a stored blob
+pub struct BlobResponse {
+    /// The content type of the blob
+    pub content_type: ContentType,
+    /// The size of the blob in bytes
+    pub size: u64,
+    /// A value that uniquely identifies the contents of the blob
+    pub etag: String,
+    /// The local time that the blob was last modified
+    pub last_modified: NaiveDateTime,
+    /// The reader for the contents of the blob
+    pub reader: Box<dyn BlobReader>,
+    /// Whether search engines should be asked not to index the blob
+    pub noindex: bool,
+    /// Whether range requests are honoured, which they are not when every request for the blob has to be counted
+    pub ranges: bool,
+    /// How the browser should present the blob
+    pub presentation: Presentation,
+}
+
+/// How a browser should present a blob, which matters for blobs that could run scripts
+#[derive(Debug, Clone, PartialEq)]
+pub enum Presentation {
+    /// Shown as it is
+    Inline,
+    /// Shown with a content security policy that stops it from running scripts or reaching the site
+    Sandboxed,
+    /// Downloaded as a file with the given name instead of being shown
+    Attachment(String),
+}
+
+/// The content security policy of sandboxed blobs, which still lets them show their own images and styles
+const SANDBOX_POLICY: &str =
+    "sandbox; default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'";
+
+/// The range of bytes that a request asked for
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// The full contents were requested, or the range could not be understood
+    Full,
+    /// An inclusive range of bytes within the contents
+    Partial(u64, u64),
+    /// The range lies outside of the contents
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parse a `Range` header for contents of the given size. Only single ranges are
+    /// supported, so anything else results in the full contents being sent
+    fn parse(header: &str, size: u64) -> ByteRange {
+        let spec = match header.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+
+        let (start, end) = match spec.find('-') {
+            Some(index) => (&spec[..index], &spec[index + 1..]),
+            None => return ByteRange::Full,
+        };
+
+        match (start.parse::<u64>(), end.parse::<u64>()) {
+            // bytes=start-end
+            (Ok(start), Ok(end)) if start <= end => {
+                if start < size {
+                    ByteRange::Partial(start, end.min(size - 1))
+                } else {
+                    ByteRange::Unsatisfiable
+                }
+            }
+            // bytes=start-
+            (Ok(start), Err(_)) if end.is_empty() => {
+                if start < size {
+                    ByteRange::Partial(start, size - 1)
+                } else {
+                    ByteRange::Unsatisfiable
+                }
+            }
+            // bytes=-suffix
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix > 0 && size > 0 {
+                    ByteRange::Partial(size - suffix.min(size), size - 1)
+                } else {
+                    ByteRange::Unsatisfiable
+                }
+            }
+            _ => ByteRange::Full,
+        }
+    }
+}
+
+impl BlobResponse {
+    /// The entity tag of the blob, quoted as it is sent in headers
+    fn quoted_etag(&self) -> String {
+        format!("\"{}\"", self.etag)
+    }
+
+    /// Check if the client already has an up to date copy of the blob
+    fn is_not_modified(&self, conditions: &Conditions, etag: &str) -> bool {
+        // If-None-Match takes precedence over If-Modified-Since
+        if let Some(if_none_match) = &conditions.if_none_match {
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag);
+        }
+
+        conditions
+            .if_modified_since
+            .as_ref()
+            .and_then(|since| NaiveDateTime::parse_from_str(since.trim(), HTTP_DATE).ok())
+            .map_or(false, |since| http_time(self.last_modified) <= since)
+    }
+
+    /// Check if a range request should be honoured, based on the `If-Range` header
+    fn is_range_valid(&self, conditions: &Conditions, etag: &str) -> bool {
+        match &conditions.if_range {
+            None => true,
+            Some(if_range) => {
+                let if_range = if_range.trim();
+
+                if_range == etag
+                    || NaiveDateTime::parse_from_str(if_range, HTTP_DATE)
+                        .map_or(false, |date| http_time(self.last_modified) == date)
+            }
+        }
+    }
+
+    /// Get the range of the blob that will be sent
+    fn range(&self, conditions: &Conditions, etag: &str) -> ByteRange {
+        match &conditions.range {
+            Some(range) if self.ranges && self.is_range_valid(conditions, etag) => {
+                ByteRange::parse(range, self.size)
+            }
+            _ => ByteRange::Full,
+        }
+    }
+
+    /// Check if responding to a request counts as a view of the blob, which is only when its contents
+    /// are sent from the start. Revalidations, `HEAD` requests and later chunks of a range request do not count
+    #[must_use]
+    pub fn is_view(&self, conditions: &Conditions) -> bool {
+        let etag = self.quoted_etag();
+
+        !conditions.head
+            && !self.is_not_modified(conditions, &etag)
+            && match self.range(conditions, &etag) {
+                ByteRange::Full | ByteRange::Partial(0, _) => true,
+                ByteRange::Partial(..) | ByteRange::Unsatisfiable => false,
+            }
+    }
+}
+
+impl<'r> Responder<'r> for BlobResponse {
+    fn respond_to(mut self, request: &Request) -> response::Result<'r> {
+        let etag = self.quoted_etag();
+        let conditions = Conditions::of(request);
+        let last_modified = http_time(self.last_modified).format(HTTP_DATE).to_string();
+
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", etag.clone())
+            .raw_header("Last-Modified", last_modified)
+            .raw_header("Accept-Ranges", if self.ranges { "bytes" } else { "none" });
+
+        if self.noindex {
+            response.raw_header("X-Robots-Tag", "noindex");
+        }
+
+        match &self.presentation {
+            Presentation::Inline => {}
+            Presentation::Sandboxed => {
+                response.raw_header("Content-Security-Policy", SANDBOX_POLICY);
+            }
+            Presentation::Attachment(filename) => {
+                response.raw_header(
+                    "Content-Disposition",
+                    format!(
+                        "attachment; filename*=UTF-8''{}",
+                        Uri::percent_encode(filename)
+                    ),
+                );
+            }
+        }
+
+        if self.is_not_modified(&conditions, &etag) {
+            return response.status(Status::NotModified).ok();
+        }
+
+        match self.range(&conditions, &etag) {
+            ByteRange::Full => response
+                .header(self.content_type)
+                .raw_body(Body::Sized(self.reader, self.size))
+                .ok(),
+            ByteRange::Partial(start, end) => {
+                self.reader.seek(SeekFrom::Start(start)).map_err(|e| {
+                    error!("Error seeking in blob: ETag: {} Error: {}", etag, e);
+
+                    Status::InternalServerError
+                })?;
+
+                let length = end - start + 1;
+
+                response
+                    .status(Status::PartialContent)
+                    .header(self.content_type)
+                    .raw_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, self.size),
+                    )
+                    .raw_body(Body::Sized(self.reader.take(length), length))
+                    .ok()
+            }
+            ByteRange::Unsatisfiable => response
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{}", self.size))
+                .ok(),
+        }
+    }
+}
+
+/// Test the parsing of range headers
+#[test]
+fn test_range_parsing() {
+    assert_eq!(
+        ByteRange::parse("bytes=0-99", 1000),
+        ByteRange::Partial(0, 99)
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=500-", 1000),
+        ByteRange::Partial(500, 999)
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=-100", 1000),
+        ByteRange::Partial(900, 999)
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=-5000", 1000),
+        ByteRange::Partial(0, 999)
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=900-5000", 1000),
+        ByteRange::Partial(900, 999)
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=1000-", 1000),
+        ByteRange::Unsatisfiable
+    );
+    assert_eq!(ByteRange::parse("bytes=0-1,5-6", 1000), ByteRange::Full);
+    assert_eq!(ByteRange::parse("lines=0-1", 1000), ByteRange::Full);
+    assert_eq!(ByteRange::parse("bytes=5-1", 1000), ByteRange::Full);
+}
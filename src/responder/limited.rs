@@ -0,0 +1,21 @@
+//! A responder for requests that have been rate limited
+
+use rocket::{
+    http::Status,
+    response::{self, Responder, Response},
+    Request,
+};
+use std::time::Duration;
+
+/// A response telling the client that it has made too many requests, and how long until it can try again
+#[derive(Debug)]
+pub struct TooManyRequests(pub Duration);
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        Response::build()
+            .status(Status::TooManyRequests)
+            .raw_header("Retry-After", self.0.as_secs().max(1).to_string())
+            .ok()
+    }
+}
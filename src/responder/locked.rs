@@ -0,0 +1,68 @@
+//! A responder for uploads and links that may be locked behind a password
+
+use crate::{config::Config, guard::unlock::Attempt, templates::page::PasswordTemplate};
+use rocket::{
+    http::Status,
+    response::{self, Responder, Response},
+    Request,
+};
+
+/// A response that is either the requested resource or a prompt for its password
+pub enum Locked<'r, T: Responder<'r>> {
+    /// The resource, which either has no password or has been unlocked
+    Unlocked(T),
+    /// A prompt for the password of the resource, responded with the given status
+    Prompt(PasswordTemplate<'r>, Status),
+}
+
+impl<'r, T: Responder<'r>> Responder<'r> for Locked<'r, T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Self::Unlocked(resource) => resource.respond_to(request),
+            Self::Prompt(template, status) => Response::build_from(template.respond_to(request)?)
+                .status(status)
+                .ok(),
+        }
+    }
+}
+
+impl<'r, T: Responder<'r>> Locked<'r, T> {
+    /// A prompt for the password of a resource that has not been unlocked
+    pub fn prompt(config: &'r Config, resource_type: &'r str, action: String) -> Self {
+        Self::Prompt(
+            PasswordTemplate {
+                config,
+                resource_type,
+                action,
+                message: None,
+            },
+            Status::Unauthorized,
+        )
+    }
+
+    /// A prompt for the password of a resource after an attempt to unlock it failed
+    pub fn retry(
+        config: &'r Config,
+        resource_type: &'r str,
+        action: String,
+        attempt: Attempt,
+    ) -> Self {
+        let (message, status) = match attempt {
+            Attempt::Limited(_) => (
+                "Too many incorrect passwords, try again later",
+                Status::TooManyRequests,
+            ),
+            _ => ("Incorrect password", Status::Unauthorized),
+        };
+
+        Self::Prompt(
+            PasswordTemplate {
+                config,
+                resource_type,
+                action,
+                message: Some(message),
+            },
+            status,
+        )
+    }
+}
@@ -1,3 +1,6 @@
 //! Rocket responders
 
+pub mod blob;
 pub mod dor;
+pub mod limited;
+pub mod locked;
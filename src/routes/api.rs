@@ -0,0 +1,320 @@
+//! Routes for the versioned JSON API
+
+use crate::{
+    config::Config,
+    database::{Database, Link, LinkHits, UploadMetadata, Visibility},
+    guard::{auth::Auth, expiry::Expiry, signature::sign},
+    id::ID,
+    routes::upload,
+    storage::Storage,
+    unix_timestamp,
+};
+use chrono::{Duration, Local};
+use rocket::{
+    http::{RawStr, Status},
+    State,
+};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+/// The details of an upload returned from the api
+#[derive(Serialize)]
+pub struct UploadInfo {
+    id: ID,
+    filename: String,
+    size: u64,
+    /// Unix timestamp of when the upload was created
+    timestamp: i64,
+    owner: Option<String>,
+    /// Unix timestamp of when the upload expires
+    expires_at: Option<i64>,
+    max_views: Option<u32>,
+    /// Whether a password is needed to view the upload
+    protected: bool,
+    visibility: Visibility,
+    /// SHA-256 hash of the upload's contents
+    sha256: String,
+    /// MIME type detected from the upload's contents
+    content_type: String,
+}
+
+impl From<UploadMetadata> for UploadInfo {
+    fn from(upload: UploadMetadata) -> Self {
+        UploadInfo {
+            id: upload.id,
+            filename: upload.filename,
+            size: upload.size,
+            timestamp: unix_timestamp(upload.timestamp),
+            owner: upload.owner,
+            expires_at: upload.expires_at.map(unix_timestamp),
+            max_views: upload.max_views,
+            protected: upload.password.is_some(),
+            visibility: upload.visibility,
+            sha256: upload.sha256,
+            content_type: upload.content_type,
+        }
+    }
+}
+
+/// The details of a link returned from the api
+#[derive(Serialize)]
+pub struct LinkInfo {
+    id: ID,
+    uri: String,
+    /// Unix timestamp of when the link was created
+    timestamp: i64,
+    owner: Option<String>,
+    hits: LinkHits,
+    /// Unix timestamp of when the link expires
+    expires_at: Option<i64>,
+    max_hits: Option<u32>,
+    /// Whether a password is needed to follow the link
+    protected: bool,
+}
+
+impl From<(Link, LinkHits)> for LinkInfo {
+    fn from((link, hits): (Link, LinkHits)) -> Self {
+        LinkInfo {
+            id: link.id,
+            uri: link.uri,
+            timestamp: unix_timestamp(link.timestamp),
+            owner: link.owner,
+            hits,
+            expires_at: link.expires_at.map(unix_timestamp),
+            max_hits: link.max_hits,
+            protected: link.password.is_some(),
+        }
+    }
+}
+
+/// The statistics of the server returned from the api
+#[derive(Serialize)]
+pub struct Stats {
+    uploads: u64,
+    upload_size: u64,
+    upload_views: u64,
+    links: u64,
+    link_hits: LinkHits,
+}
+
+/// A time-limited url to an upload returned from the api
+#[derive(Serialize)]
+pub struct SignedUrl {
+    url: String,
+    /// Unix timestamp of when the url stops working
+    expires_at: i64,
+}
+
+/// How long signed urls last for when no lifetime is given, in hours
+const SIGNED_URL_HOURS: i64 = 24;
+
+/// Helper fn to reject requests that are not authenticated
+fn authenticated(auth: Option<Auth>) -> Result<(), Status> {
+    auth.map(|_| ()).ok_or(Status::Unauthorized)
+}
+
+/// Endpoint to list all uploads
+#[get("/uploads")]
+pub fn uploads(auth: Option<Auth>, database: Database) -> Result<Json<Vec<UploadInfo>>, Status> {
+    authenticated(auth)?;
+
+    let uploads = database.uploads().get_all_uploads().map_err(|e| {
+        error!("Error indexing uploads: {}", e);
+
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(
+        uploads
+            .into_vec()
+            .into_iter()
+            .map(UploadInfo::from)
+            .collect(),
+    ))
+}
+
+/// Endpoint to get the details of an upload
+#[get("/uploads/<id>")]
+pub fn upload(auth: Option<Auth>, database: Database, id: ID) -> Result<Json<UploadInfo>, Status> {
+    authenticated(auth)?;
+
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => Ok(Json(metadata.into())),
+    }
+}
+
+/// Endpoint to create a signed url that can view an upload until it expires,
+/// even if the upload is private or password protected
+#[post("/uploads/<id>/sign?<expires>")]
+pub fn sign_upload(
+    auth: Option<Auth>,
+    config: State<Config>,
+    database: Database,
+    expiry: Expiry,
+    id: ID,
+    expires: Option<&RawStr>,
+) -> Result<Json<SignedUrl>, Status> {
+    authenticated(auth)?;
+
+    let key = match &config.signing_key {
+        Some(key) => key,
+        None => {
+            warn!("Tried to sign a url without a `signing_key` in the config");
+
+            return Err(Status::NotImplemented);
+        }
+    };
+    let lifetime = expiry
+        .lifetime(expires)?
+        .unwrap_or_else(|| Duration::hours(SIGNED_URL_HOURS));
+
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => {
+            let expires_at = unix_timestamp(Local::now().naive_local() + lifetime);
+            let signature = sign(key, &id, &metadata.filename, expires_at);
+
+            Ok(Json(SignedUrl {
+                url: format!(
+                    "http{}://{}{}?exp={}&sig={}",
+                    if config.https { "s" } else { "" },
+                    config.domain,
+                    uri!(upload::view: &id, metadata.filename),
+                    expires_at,
+                    signature
+                ),
+                expires_at,
+            }))
+        }
+    }
+}
+
+/// Endpoint to delete an upload
+#[delete("/uploads/<id>")]
+pub fn delete_upload(
+    auth: Option<Auth>,
+    database: Database,
+    storage: State<Box<dyn Storage>>,
+    id: ID,
+) -> Result<Status, Status> {
+    authenticated(auth)?;
+
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => {
+            upload::remove(&database, storage.as_ref(), &metadata)?;
+
+            Ok(Status::NoContent)
+        }
+    }
+}
+
+/// Endpoint to list all links
+#[get("/links")]
+pub fn links(auth: Option<Auth>, database: Database) -> Result<Json<Vec<LinkInfo>>, Status> {
+    authenticated(auth)?;
+
+    let links = database.links().get_all_links().map_err(|e| {
+        error!("Error indexing links: {}", e);
+
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(
+        links.into_vec().into_iter().map(LinkInfo::from).collect(),
+    ))
+}
+
+/// Endpoint to get the details of a link
+#[get("/links/<id>")]
+pub fn link(auth: Option<Auth>, database: Database, id: ID) -> Result<Json<LinkInfo>, Status> {
+    authenticated(auth)?;
+
+    match database.links().get_link(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching link: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(listing) => Ok(Json(listing.into())),
+    }
+}
+
+/// Endpoint to delete a link
+#[delete("/links/<id>")]
+pub fn delete_link(auth: Option<Auth>, database: Database, id: ID) -> Result<Status, Status> {
+    authenticated(auth)?;
+
+    match database.links().get_link(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching link: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok((link, _)) => {
+            database.links().delete_link(&id).map_err(|e| {
+                error!(
+                    "Error deleting link: ID: {} Uri: {} Error: {}",
+                    id, link.uri, e
+                );
+
+                Status::InternalServerError
+            })?;
+
+            Ok(Status::NoContent)
+        }
+    }
+}
+
+/// Endpoint to get the statistics of the server
+#[get("/stats")]
+pub fn stats(auth: Option<Auth>, database: Database) -> Result<Json<Stats>, Status> {
+    authenticated(auth)?;
+
+    let uploads = database.uploads();
+    let links = database.links();
+
+    let upload_stats = uploads
+        .count()
+        .and_then(|count| Ok((count, uploads.total_size()?, uploads.total_views()?)));
+    let (upload_count, upload_size, upload_views) = upload_stats.map_err(|e| {
+        error!("Error counting uploads: {}", e);
+
+        Status::InternalServerError
+    })?;
+
+    let link_stats = links
+        .count()
+        .and_then(|count| Ok((count, links.total_hits()?)));
+    let (link_count, link_hits) = link_stats.map_err(|e| {
+        error!("Error counting links: {}", e);
+
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(Stats {
+        uploads: upload_count,
+        upload_size,
+        upload_views,
+        links: link_count,
+        link_hits,
+    }))
+}
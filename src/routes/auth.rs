@@ -1,11 +1,22 @@
 //! Routes for handling authentication
 
 use crate::{
-    config::Config, guard::auth::Auth, responder::dor::DOR, routes::rocket_uri_macro_index,
-    templates::page::LoginTemplate, user::User,
+    config::Config,
+    database::{Database, Session},
+    generate::generate_base64,
+    guard::{
+        auth::{session_id, Auth, SESSION_COOKIE, SESSION_DAYS},
+        client::{ClientIp, UserAgent},
+    },
+    limiter::LoginLimiter,
+    responder::{dor::DOR, limited::TooManyRequests},
+    routes::rocket_uri_macro_index,
+    templates::page::{LoginTemplate, SessionsTemplate},
+    user::Login,
 };
+use chrono::{Duration, Local};
 use rocket::{
-    http::{uri::Uri, Cookie, Cookies, Status},
+    http::{uri::Uri, Cookie, Cookies, SameSite, Status},
     request::Form,
     response::Redirect,
     State,
@@ -33,34 +44,154 @@ pub fn login_form<'r>(
 
 /// The login submission portal
 #[post("/login", data = "<user>")]
-pub fn login_submit(mut cookies: Cookies, config: State<Config>, user: Form<User>) -> Status {
+pub fn login_submit(
+    mut cookies: Cookies,
+    config: State<Config>,
+    database: Database,
+    limiter: State<LoginLimiter>,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+    user: Form<Login>,
+) -> Result<Status, TooManyRequests> {
+    // Refuse to check the credentials if there have been too many failed attempts
+    if let Some(retry_after) = limiter.check(*client_ip, &user.username) {
+        info!(
+            "Blocked login attempt: Username: {} IP: {:?}",
+            user.username, *client_ip
+        );
+
+        return Err(TooManyRequests(retry_after));
+    }
+
     // Check if the user submitted exixts
-    if config.users.iter().any(|u| *u == *user) {
-        // If the user exists, add the cookie with their authentication information
-        cookies.add(
-            Cookie::build(
-                "auth",
-                base64::encode_config(
-                    format!("{}:{}", user.username, user.password),
-                    base64::URL_SAFE,
-                ),
-            )
-            .permanent()
-            .finish(),
+    if config
+        .authenticate(&user.username, &user.password)
+        .is_some()
+    {
+        limiter.succeed(*client_ip, &user.username);
+
+        let now = Local::now().naive_local();
+        let sessions = database.sessions();
+
+        // Clean up any old sessions while we are here
+        if let Err(e) = sessions.delete_expired(now) {
+            warn!("Error deleting expired sessions: {}", e);
+        }
+
+        // Create a new session for the user, only storing the hash of its token
+        let token = generate_base64(44);
+        let session = Session {
+            id: session_id(&token),
+            username: user.username.clone(),
+            created: now,
+            expires: now + Duration::days(SESSION_DAYS),
+            user_agent: (*user_agent).clone(),
+        };
+
+        if let Err(e) = sessions.save_session(&session) {
+            error!(
+                "Error saving session: Username: {} Error: {}",
+                session.username, e
+            );
+
+            return Ok(Status::InternalServerError);
+        }
+
+        // Add the private cookie with the session token, which expires at the same time as the session
+        cookies.add_private(
+            Cookie::build(SESSION_COOKIE, token)
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .secure(config.https)
+                .finish(),
         );
 
         // Return a successful status to the async loginer
-        Status::Accepted
+        Ok(Status::Accepted)
     } else {
-        // Return a failure if the user does not exist
-        Status::NotAcceptable
+        warn!(
+            "Failed login attempt: Username: {} IP: {:?}",
+            user.username, *client_ip
+        );
+
+        // Lock out further attempts if there have been too many failures
+        match limiter.fail(*client_ip, &user.username) {
+            Some(retry_after) => Err(TooManyRequests(retry_after)),
+            // Return a failure if the user does not exist
+            None => Ok(Status::NotAcceptable),
+        }
     }
 }
 
 /// The logout flow
 #[get("/logout?<redirect>")]
-pub fn logout(mut cookies: Cookies, redirect: Option<String>) -> Redirect {
+pub fn logout(mut cookies: Cookies, database: Database, redirect: Option<String>) -> Redirect {
+    // Invalidate the session on the server as well as removing the cookie
+    if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+        if let Err(e) = database.sessions().end_session(&session_id(cookie.value())) {
+            error!("Error deleting session on logout: {}", e);
+        }
+    }
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+
+    // Remove the credentials cookie left behind by older versions
     cookies.remove(Cookie::named("auth"));
 
     Redirect::to(redirect.unwrap_or_else(|| "/".into()))
 }
+
+/// Endpoint to view the logged in user's active sessions
+#[get("/sessions")]
+pub fn sessions<'r>(
+    auth: Option<Auth<'r>>,
+    mut cookies: Cookies,
+    config: State<'r, Config>,
+    database: Database,
+) -> Result<DOR<'r, SessionsTemplate<'r>>, Status> {
+    let user = match auth.as_ref().and_then(Auth::user) {
+        Some(user) => user,
+        None => return Ok(DOR::login_and_return(uri!(sessions))),
+    };
+
+    Ok(DOR::data(SessionsTemplate {
+        config: config.inner(),
+        sessions: database
+            .sessions()
+            .get_user_sessions(&user.username, Local::now().naive_local())
+            .map_err(|e| {
+                error!("Error indexing sessions: {}", e);
+
+                Status::InternalServerError
+            })?,
+        current: cookies
+            .get_private(SESSION_COOKIE)
+            .map(|cookie| session_id(cookie.value()))
+            .unwrap_or_default(),
+    }))
+}
+
+/// Endpoint to revoke one of the logged in user's sessions
+#[get("/sessions/revoke/<id>")]
+pub fn revoke_session<'r>(
+    auth: Option<Auth<'r>>,
+    database: Database,
+    id: String,
+) -> Result<DOR<'r, ()>, Status> {
+    let user = match auth.as_ref().and_then(Auth::user) {
+        Some(user) => user,
+        None => return Ok(DOR::login_and_return(uri!(revoke_session: id))),
+    };
+
+    match database.sessions().delete_session(&id, &user.username) {
+        Err(e) => {
+            error!(
+                "Error revoking session: Username: {} Error: {}",
+                user.username, e
+            );
+
+            Err(Status::InternalServerError)
+        }
+        Ok(false) => Err(Status::NotFound),
+        Ok(true) => Ok(DOR::redirect(uri!(sessions))),
+    }
+}
@@ -2,7 +2,9 @@
 
 use crate::{
     config::Config,
-    templates::error::{InternalErrorTemplate, PageNotFoundTemplate, UnauthorizedTemplate},
+    templates::error::{
+        GoneTemplate, InternalErrorTemplate, PageNotFoundTemplate, UnauthorizedTemplate,
+    },
 };
 use rocket::{Request, State};
 
@@ -26,6 +28,15 @@ pub fn unauthorized(req: &Request) -> UnauthorizedTemplate {
     }
 }
 
+/// Catcher for when an upload or link has expired and throws a 410
+#[catch(410)]
+pub fn gone(req: &Request) -> GoneTemplate {
+    GoneTemplate {
+        uri: req.uri().path().into(),
+        config: req.guard::<State<Config>>().unwrap().inner().clone(),
+    }
+}
+
 /// Catcher for an irrivecoverable internal error
 #[catch(500)]
 pub fn internal_error(req: &Request) -> InternalErrorTemplate {
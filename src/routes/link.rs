@@ -2,15 +2,25 @@
 
 use crate::{
     config::Config,
-    database::{Database, Link},
-    guard::auth::Auth,
+    database::{
+        page::{Cursor, Day, LinkFilter, LinkSort, Order, Position},
+        Access, Database, Link, ResourceKind,
+    },
+    guard::{
+        auth::Auth,
+        client::Visitor,
+        expiry::Expiry,
+        unlock::{Attempt, Unlock, Unlocker},
+    },
     id::ID,
-    responder::dor::DOR,
-    templates::page::{DeletedTemplate, LinksTemplate},
+    responder::{dor::DOR, locked::Locked},
+    routes::{hash_share_password, page_link, parse_limit, PasswordForm},
+    templates::page::{DeletedTemplate, LinksTemplate, ProtectTemplate},
 };
 use chrono::Local;
 use rocket::{
-    http::{uri::Uri, Status},
+    http::{uri::Uri, RawStr, Status},
+    request::{Form, LenientForm},
     response::Redirect,
     State,
 };
@@ -24,14 +34,34 @@ pub struct LinkResult {
 }
 
 /// Endpoint to shorten a url
-#[post("/l?<uri>")]
-pub fn create(_auth: Auth, database: Database, uri: String) -> Result<Json<LinkResult>, Status> {
+#[post("/l?<uri>&<expires>&<max_hits>&<password>")]
+pub fn create(
+    auth: Auth,
+    database: Database,
+    expiry: Expiry,
+    uri: String,
+    expires: Option<&RawStr>,
+    max_hits: Option<&RawStr>,
+    password: Option<String>,
+) -> Result<Json<LinkResult>, Status> {
+    // Get how long the link should last before it expires
+    let lifetime = expiry.lifetime(expires)?;
+    // Get how many times the link can be followed before it is deleted
+    let max_hits = parse_limit(max_hits)?;
+    // Hash the password needed to follow the link if it should be protected
+    let password = hash_share_password(password.as_deref())?;
+
     match Uri::parse(&uri) {
         Ok(_) => {
+            let timestamp = Local::now().naive_local();
             let link = Link {
                 id: ID::new(),
                 uri,
-                timestamp: Local::now().naive_local(),
+                timestamp,
+                owner: auth.owner(),
+                expires_at: lifetime.map(|lifetime| timestamp + lifetime),
+                max_hits,
+                password,
             };
 
             database.links().save_link(&link).map_err(|e| {
@@ -53,29 +83,101 @@ pub fn create(_auth: Auth, database: Database, uri: String) -> Result<Json<LinkR
     }
 }
 
+/// The query parameters used to sort, filter and page through the links
+#[derive(FromForm, Default)]
+pub struct LinkQuery {
+    sort: Option<LinkSort>,
+    order: Option<Order>,
+    uri: Option<String>,
+    from: Option<Day>,
+    to: Option<Day>,
+    after: Option<String>,
+    before: Option<String>,
+}
+
+impl LinkQuery {
+    /// The filter described by the query
+    fn filter(&self) -> LinkFilter {
+        LinkFilter {
+            uri: self.uri.clone().filter(|uri| !uri.is_empty()),
+            from: self.from,
+            to: self.to,
+        }
+    }
+
+    /// Build the url to the page before or after a cursor, keeping the sort and filter
+    fn page_link(&self, direction: &str, cursor: &Cursor) -> String {
+        let filter = self.filter();
+
+        page_link(
+            "/l",
+            &[
+                ("sort", self.sort.map(|sort| sort.name().into())),
+                ("order", self.order.map(|order| order.name().into())),
+                ("uri", filter.uri),
+                ("from", filter.from.map(|day| day.to_string())),
+                ("to", filter.to.map(|day| day.to_string())),
+                (direction, Some(cursor.to_string())),
+            ],
+        )
+    }
+}
+
 /// Endpoint to view shortened urls
-#[get("/l")]
+#[get("/l?<query..>")]
 pub fn all<'r>(
     auth: Option<Auth<'r>>,
     config: State<'r, Config>,
     database: Database,
+    query: Option<LenientForm<LinkQuery>>,
 ) -> Result<DOR<'r, LinksTemplate<'r>>, Status> {
-    Ok(match auth {
-        Some(_) => DOR::data(LinksTemplate {
-            links: database.links().get_all_links().map_err(|e| {
-                error!("Error indexing links: {}", e);
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(all: _)));
+    }
 
-                Status::InternalServerError
-            })?,
-            config: config.inner(),
-        }),
-        None => DOR::login_and_return(uri!(all)),
-    })
+    let query = query.map(LenientForm::into_inner).unwrap_or_default();
+    let sort = query.sort.unwrap_or_default();
+    let order = query.order.unwrap_or_default();
+    let filter = query.filter();
+    let position = Position::parse(
+        query.after.as_deref(),
+        query.before.as_deref(),
+        sort.numeric(),
+    )
+    .ok_or(Status::BadRequest)?;
+
+    let page = database
+        .links()
+        .get_links_page(sort, order, &filter, position)
+        .map_err(|e| {
+            error!("Error indexing links: {}", e);
+
+            Status::InternalServerError
+        })?;
+
+    Ok(DOR::data(LinksTemplate {
+        config: config.inner(),
+        links: page.items.into_boxed_slice(),
+        previous: page
+            .previous
+            .map(|cursor| query.page_link("before", &cursor)),
+        next: page.next.map(|cursor| query.page_link("after", &cursor)),
+        filter,
+        sort,
+        order,
+    }))
 }
 
 /// Endpoint to use a shortened link
 #[get("/l/<id>")]
-pub fn follow(database: Database, id: ID) -> Result<Redirect, Status> {
+pub fn follow<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    unlock: Unlock,
+    visitor: Option<Visitor>,
+    id: ID,
+) -> Result<Locked<'r, Redirect>, Status> {
     let links = database.links();
     match links.get_link(&id) {
         Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
@@ -85,13 +187,130 @@ pub fn follow(database: Database, id: ID) -> Result<Redirect, Status> {
             Err(Status::InternalServerError)
         }
         Ok((link, _)) => {
-            links.hit(&id).map_err(|e| {
+            if link.is_expired(Local::now().naive_local()) {
+                return Err(Status::Gone);
+            }
+
+            // Ask for the password before using up any hits
+            if auth.is_none() && !unlock.allows(&id, link.password.as_deref()) {
+                return Ok(Locked::prompt(
+                    config.inner(),
+                    "link",
+                    uri!(follow: &id).to_string(),
+                ));
+            }
+
+            let access = links.hit(&id).map_err(|e| {
                 error!("Error incrementing hits on link: ID: {} Error: {}", id, e);
 
                 Status::InternalServerError
             })?;
-            Ok(Redirect::to(link.uri))
+
+            match access {
+                Access::Exhausted => return Err(Status::Gone),
+                Access::Last => {
+                    // The link can still be followed this one last time if deleting it fails,
+                    // as the reaper will clean it up later
+                    if let Err(e) = links.delete_link(&id) {
+                        error!("Error deleting used up link: ID: {} Error: {}", id, e);
+                    }
+                }
+                Access::Allowed => {}
+            }
+
+            if let Some(visitor) = visitor {
+                let record = visitor.access(ResourceKind::Link, &id);
+                if let Err(e) = database.accesses().record(&record) {
+                    error!("Error logging access to link: ID: {} Error: {}", id, e);
+                }
+            }
+
+            Ok(Locked::Unlocked(Redirect::to(link.uri)))
+        }
+    }
+}
+
+/// Endpoint to unlock a password protected link with its password
+#[post("/l/<id>", data = "<form>")]
+pub fn unlock<'r>(
+    config: State<'r, Config>,
+    database: Database,
+    mut unlocker: Unlocker,
+    id: ID,
+    form: Form<PasswordForm>,
+) -> Result<Locked<'r, Redirect>, Status> {
+    match database.links().get_link(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching link: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok((link, _)) => {
+            let action = uri!(follow: &id).to_string();
+            match &link.password {
+                // Links without a password do not need to be unlocked
+                None => Ok(Locked::Unlocked(Redirect::to(action))),
+                Some(password) => {
+                    match unlocker.attempt(&id, password, &form.password, action.clone()) {
+                        Attempt::Unlocked => Ok(Locked::Unlocked(Redirect::to(action))),
+                        attempt => Ok(Locked::retry(config.inner(), "link", action, attempt)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The page to set or remove the password of a link
+#[get("/l/p/<id>")]
+pub fn protect<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    id: ID,
+) -> Result<DOR<'r, ProtectTemplate<'r>>, Status> {
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(protect: id)));
+    }
+
+    match database.links().get_link(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching link: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok((link, _)) => Ok(DOR::data(ProtectTemplate {
+            config: config.inner(),
+            resource_type: "link",
+            name: link.uri,
+            protected: link.password.is_some(),
+        })),
+    }
+}
+
+/// Endpoint to set or remove the password of a link, where an empty password removes it
+#[post("/l/p/<id>", data = "<form>")]
+pub fn protect_submit(
+    auth: Option<Auth>,
+    database: Database,
+    id: ID,
+    form: Form<PasswordForm>,
+) -> Result<Redirect, Status> {
+    if auth.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let password = hash_share_password(Some(&form.password))?;
+    match database.links().set_password(&id, password.as_deref()) {
+        Err(e) => {
+            error!("Error setting link password: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
         }
+        Ok(false) => Err(Status::NotFound),
+        Ok(true) => Ok(Redirect::to(uri!(all: _))),
     }
 }
 
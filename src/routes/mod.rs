@@ -3,19 +3,31 @@
 use crate::guard::auth::Auth;
 
 use crate::{
-    config::Config, database::Database, responder::dor::DOR, templates::page::IndexTemplate,
+    config::Config,
+    database::Database,
+    responder::dor::DOR,
+    templates::{
+        page::{IndexTemplate, QuotaUsage},
+        uploader::{ShortenerTemplate, UploaderTemplate},
+    },
+    user::hash_password,
+    viewer::Highlighter,
 };
+use askama::Template;
 use rocket::{
-    http::{ContentType, Status},
+    http::{uri::Uri, ContentType, RawStr, Status},
     response::content::Content,
     State,
 };
 use rust_embed::RustEmbed;
-use std::{convert::TryInto, fs, io::ErrorKind, path::PathBuf};
+use std::path::PathBuf;
 
+pub mod api;
 pub mod auth;
 pub mod catcher;
 pub mod link;
+pub mod search;
+pub mod stats;
 pub mod upload;
 
 /// The main page
@@ -28,50 +40,94 @@ pub fn index<'r>(
     match auth {
         None => Ok(DOR::login()),
         Some(_) => {
-            let uploads = database.uploads().get_all_uploads().map_err(|e| {
-                error!("Error indexing uploads: {}", e);
+            let uploads = database.uploads();
+            let links = database.links();
+
+            let upload_stats = uploads
+                .count()
+                .and_then(|count| Ok((count, uploads.total_size()?, uploads.total_views()?)));
+            let (upload_count, space_count, view_count) = upload_stats.map_err(|e| {
+                error!("Error counting uploads: {}", e);
 
                 Status::InternalServerError
             })?;
-            let links = database.links().get_all_links().map_err(|e| {
-                error!("Error indexing links: {}", e);
+
+            let mut quotas = uploads
+                .owned_sizes()
+                .map(|sizes| {
+                    config
+                        .users
+                        .iter()
+                        .map(|user| QuotaUsage {
+                            username: &user.username,
+                            used: sizes.get(&user.username).copied().unwrap_or(0),
+                            limited: user.quota.is_some(),
+                            quota: user.quota.unwrap_or(0),
+                        })
+                        .collect::<Box<[_]>>()
+                })
+                .map_err(|e| {
+                    error!("Error getting storage used by users: {}", e);
+
+                    Status::InternalServerError
+                })?;
+            quotas.sort_by_key(|usage| usage.username);
+
+            let link_stats = links
+                .count()
+                .and_then(|count| Ok((count, links.total_hits()?)));
+            let (link_count, total_hits) = link_stats.map_err(|e| {
+                error!("Error counting links: {}", e);
 
                 Status::InternalServerError
             })?;
 
             Ok(DOR::data(IndexTemplate {
                 config: config.inner(),
-                link_count: links.len().try_into().map_err(|e| {
-                    error!("Error converting usize to u64: {}", e);
-
-                    Status::InternalServerError
-                })?,
-                upload_count: uploads.len().try_into().map_err(|e| {
-                    error!("Error converting usize to u64: {}", e);
-
-                    Status::InternalServerError
-                })?,
-                space_count: uploads.iter().fold(0, |acc, b| acc + b.size),
-                total_hits: links.iter().fold(0, |acc, (_, hits)| acc + hits),
+                link_count,
+                upload_count,
+                space_count,
+                view_count,
+                total_hits,
+                quotas,
             }))
         }
     }
 }
 
-/// The urls to download the uploader templates from
+/// The urls to download the uploader templates from, containing the logged in user's token
 #[get("/sxcu/<filename>")]
-pub fn uploaders(
-    auth: Option<Auth>,
+pub fn uploaders<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
     filename: String,
 ) -> Result<DOR<'static, Content<String>>, Status> {
-    if auth.is_none() {
-        return Ok(DOR::login_and_return(uri!(uploaders: filename)));
-    }
+    let user = match auth.as_ref().and_then(Auth::user) {
+        Some(user) => user,
+        None => return Ok(DOR::login_and_return(uri!(uploaders: filename))),
+    };
+
+    let token = match user.tokens.first() {
+        Some(token) => token,
+        None => {
+            warn!(
+                "User {} tried to download an uploader without having an upload token",
+                user.username
+            );
+
+            return Err(Status::NotFound);
+        }
+    };
+
+    let rendered = match filename.as_str() {
+        "uploader.sxcu" => UploaderTemplate::new(config.inner(), token).render(),
+        "shortener.sxcu" => ShortenerTemplate::new(config.inner(), token).render(),
+        _ => return Err(Status::NotFound),
+    };
 
-    match fs::read_to_string(format!("data/uploaders/{}", filename)) {
-        Err(e) if e.kind() == ErrorKind::NotFound => Err(Status::NotFound),
+    match rendered {
         Err(e) => {
-            error!("Error reading uploader file {} {}", filename, e);
+            error!("Error rendering uploader file {} {}", filename, e);
 
             Err(Status::InternalServerError)
         }
@@ -79,6 +135,12 @@ pub fn uploaders(
     }
 }
 
+/// The stylesheet that colours the code shown on preview pages
+#[get("/highlight.css")]
+pub fn highlight_css(highlighter: State<Highlighter>) -> Content<String> {
+    Content(ContentType::CSS, highlighter.stylesheet())
+}
+
 /// Endpoint to acces static files
 #[get("/<filename..>", rank = 100)]
 pub fn public_files(filename: PathBuf) -> Option<Content<Vec<u8>>> {
@@ -111,3 +173,58 @@ pub fn upload_url(config: State<Config>) -> String {
         .clone()
         .unwrap_or_else(|| config.domain.clone())
 }
+
+/// Build the url to a page of a listing, keeping any query parameters that are set
+pub fn page_link(path: &str, params: &[(&str, Option<String>)]) -> String {
+    let query = params
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|value| format!("{}={}", name, Uri::percent_encode(value)))
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    format!("{}?{}", path, query)
+}
+
+/// Parse the limit on the amount of times an upload or link can be accessed from a query parameter
+///
+/// # Errors
+/// - If the limit is not a positive number, with a 400 status
+pub fn parse_limit(limit: Option<&RawStr>) -> Result<Option<u32>, Status> {
+    match limit {
+        None => Ok(None),
+        Some(limit) => match limit.parse() {
+            Ok(limit) if limit > 0 => Ok(Some(limit)),
+            _ => {
+                warn!("Invalid access limit: {}", limit);
+
+                Err(Status::BadRequest)
+            }
+        },
+    }
+}
+
+/// The password submitted to unlock an upload or link, or to set as its new password
+#[derive(FromForm, Debug)]
+pub struct PasswordForm {
+    /// The password that was entered
+    pub password: String,
+}
+
+/// Hash the password to protect an upload or link with, where an empty password means no protection
+///
+/// # Errors
+/// - If the password could not be hashed, with a 500 status
+pub fn hash_share_password(password: Option<&str>) -> Result<Option<String>, Status> {
+    match password.filter(|password| !password.is_empty()) {
+        None => Ok(None),
+        Some(password) => hash_password(password).map(Some).map_err(|e| {
+            error!("Error hashing password: {}", e);
+
+            Status::InternalServerError
+        }),
+    }
+}
@@ -0,0 +1,90 @@
+//! Routes for searching through uploads and links
+
+use crate::{
+    config::Config,
+    database::{search::match_query, Database, LinkListing, UploadMetadata},
+    guard::auth::Auth,
+    responder::dor::DOR,
+    routes::api::{LinkInfo, UploadInfo},
+    templates::page::SearchTemplate,
+};
+use rocket::{http::Status, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+/// The responded result of a search
+#[derive(Serialize)]
+pub struct SearchResult {
+    uploads: Vec<UploadInfo>,
+    links: Vec<LinkInfo>,
+}
+
+/// Helper fn to find the uploads and links matching the text typed by a user
+fn find(
+    database: &Database,
+    text: &str,
+) -> Result<(Box<[UploadMetadata]>, Box<[LinkListing]>), Status> {
+    let query = match match_query(text) {
+        Some(query) => query,
+        None => return Ok(Default::default()),
+    };
+
+    let uploads = database.uploads().search(&query).map_err(|e| {
+        error!("Error searching uploads: Query: {} Error: {}", query, e);
+
+        Status::InternalServerError
+    })?;
+    let links = database.links().search(&query).map_err(|e| {
+        error!("Error searching links: Query: {} Error: {}", query, e);
+
+        Status::InternalServerError
+    })?;
+
+    Ok((uploads, links))
+}
+
+/// Endpoint to search for uploads and links, responding with JSON
+#[get("/search?<q>", format = "json")]
+pub fn search_json(
+    auth: Option<Auth>,
+    database: Database,
+    q: Option<String>,
+) -> Result<Json<SearchResult>, Status> {
+    if auth.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let (uploads, links) = find(&database, q.as_deref().unwrap_or_default())?;
+
+    Ok(Json(SearchResult {
+        uploads: uploads
+            .into_vec()
+            .into_iter()
+            .map(UploadInfo::from)
+            .collect(),
+        links: links.into_vec().into_iter().map(LinkInfo::from).collect(),
+    }))
+}
+
+/// Endpoint to search for uploads and links
+#[get("/search?<q>", rank = 2)]
+pub fn search<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    q: Option<String>,
+) -> Result<DOR<'r, SearchTemplate<'r>>, Status> {
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(search: q)));
+    }
+
+    let query = q.unwrap_or_default();
+    let (uploads, links) = find(&database, &query)?;
+
+    Ok(DOR::data(SearchTemplate {
+        config: config.inner(),
+        query,
+        uploads,
+        links,
+    }))
+}
@@ -0,0 +1,124 @@
+//! Routes for viewing the statistics of the access log
+
+use crate::{
+    config::Config,
+    database::{AccessCount, Database},
+    guard::auth::Auth,
+    responder::dor::DOR,
+    templates::page::{DailyAccesses, StatsTemplate},
+};
+use chrono::{Duration, Local, NaiveDate};
+use rocket::{http::Status, State};
+
+/// The most days of accesses shown on the stats page
+pub const STATS_DAYS: u32 = 30;
+
+/// How many uploads, links and referrers are listed in the rankings
+pub const TOP_LIMIT: u32 = 10;
+
+/// Fill in the days without any accesses between the first and last day,
+/// scaling each day's bar against the busiest one
+#[must_use]
+pub fn fill_days(
+    counts: &[(NaiveDate, AccessCount)],
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Box<[DailyAccesses]> {
+    let busiest = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let mut counts = counts.iter().peekable();
+    let mut days = Vec::new();
+    let mut day = first;
+
+    while day <= last {
+        let mut count = 0;
+        while let Some((counted, accesses)) = counts.peek() {
+            if *counted > day {
+                break;
+            } else if *counted == day {
+                count = *accesses;
+            }
+            counts.next();
+        }
+
+        days.push(DailyAccesses {
+            day,
+            count,
+            percent: if busiest == 0 {
+                0
+            } else {
+                count * 100 / busiest
+            },
+        });
+        day = day.succ();
+    }
+
+    days.into_boxed_slice()
+}
+
+/// The page showing how the uploads and links have been accessed recently
+#[get("/stats")]
+pub fn stats<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+) -> Result<DOR<'r, StatsTemplate<'r>>, Status> {
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(stats)));
+    }
+
+    // Only show as far back as the access log is kept for
+    let days = STATS_DAYS.min(config.access_log_days).max(1);
+    let today = Local::now().naive_local().date();
+    let first = today - Duration::days(i64::from(days) - 1);
+    let since = first.and_hms(0, 0, 0);
+
+    let accesses = database.accesses();
+    let counts = accesses.daily_counts(since).map_err(|e| {
+        error!("Error counting daily accesses: {}", e);
+
+        Status::InternalServerError
+    })?;
+    let rankings = accesses.top_uploads(since, TOP_LIMIT).and_then(|uploads| {
+        Ok((
+            uploads,
+            accesses.top_links(since, TOP_LIMIT)?,
+            accesses.top_referrers(since, TOP_LIMIT)?,
+            accesses.unique_visitors(since)?,
+        ))
+    });
+    let (top_uploads, top_links, top_referrers, unique_visitors) = rankings.map_err(|e| {
+        error!("Error ranking accesses: {}", e);
+
+        Status::InternalServerError
+    })?;
+
+    Ok(DOR::data(StatsTemplate {
+        config: config.inner(),
+        enabled: config.access_log_days > 0,
+        total: counts.iter().map(|(_, count)| count).sum(),
+        days: fill_days(&counts, first, today),
+        top_uploads,
+        top_links,
+        top_referrers,
+        unique_visitors,
+    }))
+}
+
+/// Test filling in the days without accesses
+#[test]
+fn test_fill_days() {
+    let day = |d| NaiveDate::from_ymd(2020, 1, d);
+    let days = fill_days(&[(day(2), 4), (day(4), 2)], day(1), day(5));
+
+    assert_eq!(
+        days.iter()
+            .map(|day| (day.count, day.percent))
+            .collect::<Vec<_>>(),
+        vec![(0, 0), (4, 100), (0, 0), (2, 50), (0, 0)]
+    );
+    assert_eq!(days[0].day, day(1));
+    assert_eq!(days[4].day, day(5));
+    assert!(fill_days(&[], day(1), day(1))
+        .iter()
+        .all(|day| day.count == 0));
+}
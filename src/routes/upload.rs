@@ -1,22 +1,52 @@
 //! Routes for handling uploads
 
 use crate::{
-    config::Config,
-    database::{Database, UploadMetadata},
-    guard::{auth::Auth, content::ContentLength},
+    config::{ActiveContent, Config},
+    database::{
+        page::{ContentKind, Cursor, Day, Order, Position, UploadFilter, UploadSort},
+        Access, Database, ResourceKind, UploadMetadata, Visibility,
+    },
+    guard::{
+        auth::Auth,
+        client::{Crawler, UserContentHost, Visitor, RAW_QUERY},
+        conditions::Conditions,
+        content::ContentLength,
+        expiry::Expiry,
+        signature::{sign, Signature},
+        unlock::{Attempt, Unlock, Unlocker},
+    },
     id::ID,
-    responder::dor::DOR,
-    templates::page::{DeletedTemplate, UploadsTemplate},
+    responder::{
+        blob::{BlobResponse, Presentation},
+        dor::DOR,
+        locked::Locked,
+    },
+    routes::{hash_share_password, page_link, parse_limit, PasswordForm},
+    sniff::{self, SNIFF_LENGTH},
+    storage::Storage,
+    stream::{ExpectedLength, HeadRecorder, LimitedLength, TooLarge},
+    templates::page::{DeletedTemplate, PreviewTemplate, ProtectTemplate, UploadsTemplate},
+    thumbnail, unix_timestamp,
+    viewer::{self, Highlighter, Viewer, MAX_TEXT_SIZE},
 };
-use chrono::Local;
+use chrono::{Duration, Local, NaiveDateTime};
+use image::ImageError;
 use rocket::{
-    http::{ContentType, Status},
-    response::{Content, Redirect},
+    http::{RawStr, Status},
+    request::{Form, FromFormValue, LenientForm},
+    response::Redirect,
     Data, State,
 };
 use rocket_contrib::json::Json;
 use serde::Serialize;
-use std::{convert::TryInto, path::PathBuf};
+use std::io::{self, ErrorKind, Read};
+
+/// How many seconds the signed urls that send private or password protected active content
+/// to the user content domain last for
+pub const USER_CONTENT_SECONDS: i64 = 60;
+
+/// The name in the url of an upload's thumbnail, which uploads can not be given as their filename
+pub const THUMBNAIL_NAME: &str = "thumb";
 
 /// The responded result to a successful file upload
 #[derive(Serialize)]
@@ -26,51 +56,126 @@ pub struct UploadResult {
 }
 
 /// Endpoint to upload an asset
-#[post("/u?<filename>", data = "<upload>")]
+#[post(
+    "/u?<filename>&<expires>&<max_views>&<password>&<visibility>",
+    data = "<upload>"
+)]
 pub fn create(
-    _auth: Auth,
+    auth: Auth,
+    config: State<Config>,
     database: Database,
-    upload_size: Option<ContentLength>,
+    storage: State<Box<dyn Storage>>,
+    upload_size: ContentLength,
+    expiry: Expiry,
     filename: Option<String>,
+    expires: Option<&RawStr>,
+    max_views: Option<&RawStr>,
+    password: Option<String>,
+    visibility: Option<&RawStr>,
     upload: Data,
 ) -> Result<Json<UploadResult>, Status> {
     // Get the upload filename or create one with unknown as the name
     let filename = filename.unwrap_or_else(|| "unknown".into());
+    // The url of an upload named thumb would be taken by its thumbnail
+    if filename == THUMBNAIL_NAME {
+        warn!("Upload has the reserved filename {:?}", filename);
+
+        return Err(Status::BadRequest);
+    }
+    // Get how long the upload should last before it expires
+    let lifetime = expiry.lifetime(expires)?;
+    // Get how many times the upload can be viewed before it is deleted
+    let max_views = parse_limit(max_views)?;
+    // Hash the password needed to view the upload if it should be protected
+    let password = hash_share_password(password.as_deref())?;
+    // Get who can view the upload, falling back to the configured default
+    let visibility = parse_visibility(visibility)?.unwrap_or(config.default_visibility);
     // Generate an ID for the upload
     let id = ID::new();
-    // Get the upload size from the
-    let upload_size = upload_size.map_or(0, |u| *u);
+    // Get the upload size from the content length header if it was sent
+    let upload_size = *upload_size;
+    // Get the most bytes the upload can be, from the size limit and what is left of the user's quota
+    let limit = upload_limit(&config, &database, &auth)?;
 
-    // Stream the data into a vec that should be preallocated with the correct size
-    let mut data = Vec::with_capacity(upload_size);
-    upload.stream_to(&mut data).map_err(|e| {
-        error!("Error streaming upload: {}", e);
+    // Turn away uploads that are known to be too large before reading any of them
+    if let (Some(size), Some(limit)) = (upload_size, limit) {
+        if size > limit {
+            warn!(
+                "Upload is too large: ID: {} Filename: {} Size: {} Limit: {}",
+                id, filename, size, limit
+            );
 
-        Status::InternalServerError
+            return Err(Status::PayloadTooLarge);
+        }
+    }
+
+    // Stream the data straight into the storage backend, failing if the client disconnects early
+    // or sends more than the limit. The start is kept to detect the content type from
+    let mut stream = HeadRecorder::new(
+        LimitedLength::new(ExpectedLength::new(upload.open(), upload_size), limit),
+        SNIFF_LENGTH,
+    );
+    let blob = storage.put(&mut stream).map_err(|e| match e.kind() {
+        _ if TooLarge::caused(&e) => {
+            warn!(
+                "Upload is too large: ID: {} Filename: {} Error: {}",
+                id, filename, e
+            );
+
+            Status::PayloadTooLarge
+        }
+        ErrorKind::UnexpectedEof => {
+            warn!(
+                "Upload was cut short: ID: {} Filename: {} Error: {}",
+                id, filename, e
+            );
+
+            Status::BadRequest
+        }
+        _ => {
+            error!(
+                "Error storing file: ID: {} Filename: {} Error: {}",
+                id, filename, e
+            );
+
+            Status::InternalServerError
+        }
     })?;
 
-    let upload = UploadMetadata {
+    let content_type = sniff::detect(stream.head(), &filename).to_string();
+    let timestamp = Local::now().naive_local();
+    let mut upload = UploadMetadata {
         id,
         filename,
-        size: data
-            .len()
-            .try_into()
-            .map_err(|_| Status::InternalServerError)?,
-        timestamp: Local::now().naive_local(),
+        size: blob.size,
+        timestamp,
+        blob: blob.key,
+        sha256: blob.sha256,
+        owner: auth.owner(),
+        expires_at: lifetime.map(|lifetime| timestamp + lifetime),
+        max_views,
+        views: 0,
+        password,
+        visibility,
+        content_type,
     };
 
     // Save the upload into the database
-    database
-        .uploads()
-        .save_upload(&upload, data.as_slice())
-        .map_err(|e| {
-            error!(
-                "Error saving file: ID: {} Filename: {} Error: {}",
-                upload.id, upload.filename, e
-            );
+    let redundant = database.uploads().save_upload(&mut upload).map_err(|e| {
+        error!(
+            "Error saving file: ID: {} Filename: {} Error: {}",
+            upload.id, upload.filename, e
+        );
 
-            Status::InternalServerError
-        })?;
+        Status::InternalServerError
+    })?;
+
+    // Identical contents were already stored under another key, so the new copy is not needed
+    if let Some(blob) = redundant {
+        if let Err(e) = storage.delete(&blob) {
+            warn!("Failed to remove duplicate blob {}: {}", blob, e);
+        }
+    }
 
     // Send the result
     Ok(Json(UploadResult {
@@ -79,36 +184,150 @@ pub fn create(
     }))
 }
 
+/// Get the most bytes that a new upload can be, from the maximum upload size
+/// and what is left of the uploading user's quota
+fn upload_limit(config: &Config, database: &Database, auth: &Auth) -> Result<Option<u64>, Status> {
+    let remaining = match auth.user() {
+        Some(user) => match user.quota {
+            Some(quota) => {
+                let used = database.uploads().owned_size(&user.username).map_err(|e| {
+                    error!(
+                        "Error getting storage used by user: User: {} Error: {}",
+                        user.username, e
+                    );
+
+                    Status::InternalServerError
+                })?;
+
+                Some(quota.saturating_sub(used))
+            }
+            None => None,
+        },
+        None => None,
+    };
+
+    Ok(match (config.max_upload_size, remaining) {
+        (Some(max), Some(remaining)) => Some(max.min(remaining)),
+        (max, remaining) => max.or(remaining),
+    })
+}
+
+/// Parse who can view an upload from a query parameter
+fn parse_visibility(visibility: Option<&RawStr>) -> Result<Option<Visibility>, Status> {
+    match visibility {
+        None => Ok(None),
+        Some(visibility) => match Visibility::from_form_value(visibility) {
+            Ok(visibility) => Ok(Some(visibility)),
+            Err(_) => {
+                warn!("Invalid visibility: {}", visibility);
+
+                Err(Status::BadRequest)
+            }
+        },
+    }
+}
+
+/// The query parameters used to sort, filter and page through the uploads
+#[derive(FromForm, Default)]
+pub struct UploadQuery {
+    sort: Option<UploadSort>,
+    order: Option<Order>,
+    filename: Option<String>,
+    #[form(field = "type")]
+    kind: Option<ContentKind>,
+    from: Option<Day>,
+    to: Option<Day>,
+    after: Option<String>,
+    before: Option<String>,
+}
+
+impl UploadQuery {
+    /// The filter described by the query
+    fn filter(&self) -> UploadFilter {
+        UploadFilter {
+            filename: self
+                .filename
+                .clone()
+                .filter(|filename| !filename.is_empty()),
+            kind: self.kind,
+            from: self.from,
+            to: self.to,
+        }
+    }
+
+    /// Build the url to the page before or after a cursor, keeping the sort and filter
+    fn page_link(&self, direction: &str, cursor: &Cursor) -> String {
+        let filter = self.filter();
+
+        page_link(
+            "/u",
+            &[
+                ("sort", self.sort.map(|sort| sort.name().into())),
+                ("order", self.order.map(|order| order.name().into())),
+                ("filename", filter.filename),
+                ("type", filter.kind.map(|kind| kind.name().into())),
+                ("from", filter.from.map(|day| day.to_string())),
+                ("to", filter.to.map(|day| day.to_string())),
+                (direction, Some(cursor.to_string())),
+            ],
+        )
+    }
+}
+
 /// Endpoint to view uploaded assets
-#[get("/u")]
+#[get("/u?<query..>")]
 pub fn all<'r>(
     auth: Option<Auth<'r>>,
     config: State<'r, Config>,
     database: Database,
+    query: Option<LenientForm<UploadQuery>>,
 ) -> Result<DOR<'r, UploadsTemplate<'r>>, Status> {
-    Ok(match auth {
-        Some(_) => DOR::data(UploadsTemplate {
-            config: config.inner(),
-            uploads: database
-                .uploads()
-                .get_all_uploads()
-                .map_err(|e| {
-                    error!("Error indexing uploads: {}", e);
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(all: _)));
+    }
 
-                    Status::InternalServerError
-                })?
-                .into_vec()
-                .into_iter()
-                .enumerate()
-                .collect(),
-        }),
-        None => DOR::login_and_return(uri!(all)),
-    })
+    let query = query.map(LenientForm::into_inner).unwrap_or_default();
+    let sort = query.sort.unwrap_or_default();
+    let order = query.order.unwrap_or_default();
+    let filter = query.filter();
+    let position = Position::parse(
+        query.after.as_deref(),
+        query.before.as_deref(),
+        sort.numeric(),
+    )
+    .ok_or(Status::BadRequest)?;
+
+    let page = database
+        .uploads()
+        .get_uploads_page(sort, order, &filter, position)
+        .map_err(|e| {
+            error!("Error indexing uploads: {}", e);
+
+            Status::InternalServerError
+        })?;
+
+    Ok(DOR::data(UploadsTemplate {
+        config: config.inner(),
+        uploads: page.items.into_iter().enumerate().collect(),
+        previous: page
+            .previous
+            .map(|cursor| query.page_link("before", &cursor)),
+        next: page.next.map(|cursor| query.page_link("after", &cursor)),
+        filter,
+        sort,
+        order,
+    }))
 }
 
 /// Endpoint to access an uploaded assest by its ID
 #[get("/u/<id>")]
-pub fn view_by_id(database: Database, id: ID) -> Result<Redirect, Status> {
+pub fn view_by_id<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    unlock: Unlock,
+    id: ID,
+) -> Result<DOR<'r, Locked<'r, Redirect>>, Status> {
     match database.uploads().get_upload_metatdata(&id) {
         Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
         Err(e) => {
@@ -116,13 +335,151 @@ pub fn view_by_id(database: Database, id: ID) -> Result<Redirect, Status> {
 
             Err(Status::InternalServerError)
         }
-        Ok(meta) => Ok(Redirect::to(uri!(view: &id, meta.filename))),
+        Ok(meta) => {
+            if meta.is_expired(Local::now().naive_local()) || meta.max_views == Some(0) {
+                Err(Status::Gone)
+            } else if meta.visibility == Visibility::Private && auth.is_none() {
+                Ok(DOR::login_and_return(uri!(view_by_id: &id)))
+            } else if auth.is_none() && !unlock.allows(&id, meta.password.as_deref()) {
+                Ok(DOR::data(Locked::prompt(
+                    config.inner(),
+                    "upload",
+                    uri!(view: &id, meta.filename).to_string(),
+                )))
+            } else {
+                Ok(DOR::data(Locked::Unlocked(Redirect::to(
+                    uri!(view: &id, meta.filename),
+                ))))
+            }
+        }
+    }
+}
+
+/// The page showing an upload along with the metadata that chat apps and social media embed it with
+#[get("/v/<id>")]
+pub fn preview<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    storage: State<Box<dyn Storage>>,
+    highlighter: State<Highlighter>,
+    id: ID,
+) -> Result<DOR<'r, PreviewTemplate<'r>>, Status> {
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => {
+            if metadata.is_expired(Local::now().naive_local()) || metadata.max_views == Some(0) {
+                Err(Status::Gone)
+            } else if metadata.visibility == Visibility::Private && auth.is_none() {
+                Ok(DOR::login_and_return(uri!(preview: &id)))
+            } else if metadata.password.is_some() && auth.is_none() {
+                // The unlock cookie is scoped to the upload's urls, so ask for the password there
+                Ok(DOR::redirect(uri!(view_by_id: &id)))
+            } else {
+                let origin = format!(
+                    "http{}://{}",
+                    if config.https { "s" } else { "" },
+                    config.domain
+                );
+
+                // Crawlers fetching the media would use up the views of limited uploads
+                let mut embed =
+                    Viewer::for_upload(&metadata).filter(|_| metadata.max_views.is_none());
+                let (rendered, line_numbers) = match embed {
+                    Some(viewer) if viewer.is_text() => {
+                        match read_text(storage.as_ref(), &metadata)? {
+                            Some(text) if viewer == Viewer::Markdown => {
+                                (viewer::render_markdown(&text), String::new())
+                            }
+                            Some(text) => (
+                                highlighter.highlight(&text, &metadata.filename),
+                                viewer::line_numbers(&text),
+                            ),
+                            // Only offer the download when the text can not be shown
+                            None => {
+                                embed = None;
+
+                                Default::default()
+                            }
+                        }
+                    }
+                    _ => Default::default(),
+                };
+
+                Ok(DOR::data(PreviewTemplate {
+                    config: config.inner(),
+                    // Crawlers fetch the media with the same user agent as the page, so ask for the upload itself
+                    url: format!(
+                        "{}{}?{}",
+                        origin,
+                        uri!(view: &id, metadata.filename.as_str()),
+                        RAW_QUERY
+                    ),
+                    page_url: format!("{}{}", origin, uri!(preview: &id)),
+                    embed,
+                    rendered,
+                    line_numbers,
+                    upload: metadata,
+                }))
+            }
+        }
+    }
+}
+
+/// Helper fn to read the contents of an upload as text to show on its preview page.
+/// Nothing is read if the upload is too large to show or is not valid UTF-8
+fn read_text(storage: &dyn Storage, metadata: &UploadMetadata) -> Result<Option<String>, Status> {
+    if metadata.size > MAX_TEXT_SIZE {
+        return Ok(None);
+    }
+
+    let mut reader = storage.open(&metadata.blob).map_err(|e| {
+        error!(
+            "Error fetching file data: ID: {} Filename: {} Error: {}",
+            metadata.id, metadata.filename, e
+        );
+
+        Status::InternalServerError
+    })?;
+
+    let mut text = String::new();
+    match reader.read_to_string(&mut text) {
+        Ok(_) => Ok(Some(text)),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Ok(None),
+        Err(e) => {
+            error!(
+                "Error reading file data: ID: {} Filename: {} Error: {}",
+                metadata.id, metadata.filename, e
+            );
+
+            Err(Status::InternalServerError)
+        }
     }
 }
 
-/// Endpoint to access an uploaded assest by its ID and filename
-#[get("/u/<id>/<filename>")]
-pub fn view(database: Database, id: ID, filename: String) -> Result<Content<Vec<u8>>, Status> {
+/// Endpoint to access an uploaded assest by its ID and filename, where a signed url
+/// can be used in place of logging in. Bots that embed links are sent to the preview page,
+/// unless the `raw` query parameter is set. Ranked below thumbnails, which share its path
+#[get("/u/<id>/<filename>", rank = 2)]
+pub fn view<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    storage: State<Box<dyn Storage>>,
+    signature: Option<Signature>,
+    unlock: Unlock,
+    user_content: UserContentHost,
+    crawler: Crawler,
+    visitor: Option<Visitor>,
+    conditions: Conditions,
+    id: ID,
+    filename: String,
+) -> Result<DOR<'r, Locked<'r, BlobResponse>>, Status> {
     match database.uploads().get_upload_metatdata(&id) {
         Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
         Err(e) => {
@@ -131,31 +488,426 @@ pub fn view(database: Database, id: ID, filename: String) -> Result<Content<Vec<
             Err(Status::InternalServerError)
         }
         Ok(metadata) => {
-            if metadata.filename == filename {
-                let content_type = match PathBuf::from(filename).extension() {
-                    Some(ext) => ContentType::from_extension(ext.to_string_lossy().as_ref())
-                        .unwrap_or(ContentType::Binary),
-                    None => ContentType::Binary,
+            let now = Local::now().naive_local();
+            let authorized = auth.is_some()
+                || signature.map_or(false, |signature| signature.allows(&id, &filename, now));
+
+            if metadata.filename != filename {
+                Err(Status::NotFound)
+            } else if metadata.is_expired(now) || metadata.max_views == Some(0) {
+                Err(Status::Gone)
+            } else if metadata.visibility == Visibility::Private && !authorized {
+                Ok(DOR::login_and_return(uri!(view: &id, filename)))
+            } else if !authorized && !unlock.allows(&id, metadata.password.as_deref()) {
+                // Ask for the password before using up any views
+                Ok(DOR::data(Locked::prompt(
+                    config.inner(),
+                    "upload",
+                    uri!(view: &id, filename).to_string(),
+                )))
+            } else if *crawler
+                && metadata.visibility != Visibility::Private
+                && metadata.password.is_none()
+            {
+                // Bots fetching the link to embed it get the preview page with the metadata for the embed
+                Ok(DOR::redirect(uri!(preview: &id)))
+            } else {
+                let content_type = metadata.media_type();
+
+                // Send active content to the user content domain before using up a view here
+                if sniff::is_active(&content_type)
+                    && config.active_content == ActiveContent::Domain
+                    && !*user_content
+                {
+                    if let Some(url) = user_content_url(&config, &metadata, now) {
+                        return Ok(DOR::redirect(url));
+                    }
+                }
+
+                let presentation = presentation(&config, *user_content, &metadata);
+
+                // Open the contents before using up a view, so that they can still be sent
+                // if this view was the last one and the upload is deleted
+                let reader = storage.open(&metadata.blob).map_err(|e| {
+                    error!(
+                        "Error fetching file data: ID: {} Filename: {} Error: {}",
+                        id, metadata.filename, e
+                    );
+
+                    Status::InternalServerError
+                })?;
+
+                let response = BlobResponse {
+                    content_type,
+                    size: metadata.size,
+                    etag: metadata.sha256.clone(),
+                    last_modified: metadata.timestamp,
+                    reader,
+                    noindex: metadata.visibility != Visibility::Public,
+                    // Uploads with limited views are always sent whole, as the rest of them could
+                    // otherwise be fetched in ranges that are not counted
+                    ranges: metadata.max_views.is_none(),
+                    presentation,
                 };
 
-                match database.uploads().get_upload_data(&id) {
-                    Err(e) => {
-                        error!(
-                            "Error fetching file data: ID: {} Filename: {} Error: {}",
-                            id, metadata.filename, e
-                        );
+                // Only count requests that send the contents from the start, so that playing a video
+                // in chunks or revalidating a cached copy does not use up views
+                if response.is_view(&conditions) {
+                    let access = database.uploads().view(&id).map_err(|e| {
+                        error!("Error counting view of upload: ID: {} Error: {}", id, e);
+
+                        Status::InternalServerError
+                    })?;
+
+                    match access {
+                        Access::Exhausted => return Err(Status::Gone),
+                        Access::Last => remove(&database, storage.as_ref(), &metadata)?,
+                        Access::Allowed => {}
+                    }
 
-                        Err(Status::InternalServerError)
+                    if let Some(visitor) = visitor {
+                        let record = visitor.access(ResourceKind::Upload, &id);
+                        if let Err(e) = database.accesses().record(&record) {
+                            error!("Error logging access to upload: ID: {} Error: {}", id, e);
+                        }
                     }
-                    Ok(data) => Ok(Content(content_type, data.to_vec())),
                 }
-            } else {
-                Err(Status::NotFound)
+
+                Ok(DOR::data(Locked::Unlocked(response)))
             }
         }
     }
 }
 
+/// Helper fn to get the url of an upload on the user content domain, which is signed for a short time
+/// if it could not be viewed there otherwise. Nothing is returned if it can not be signed
+fn user_content_url(
+    config: &Config,
+    metadata: &UploadMetadata,
+    now: NaiveDateTime,
+) -> Option<String> {
+    let url = format!(
+        "http{}://{}{}",
+        if config.https { "s" } else { "" },
+        config.user_content_domain.as_ref()?,
+        uri!(view: &metadata.id, metadata.filename.as_str())
+    );
+
+    // Logins and unlocked passwords do not carry over to the user content domain
+    if metadata.visibility != Visibility::Private && metadata.password.is_none() {
+        return Some(url);
+    }
+
+    let key = config.signing_key.as_ref()?;
+    let expires = unix_timestamp(now + Duration::seconds(USER_CONTENT_SECONDS));
+
+    Some(format!(
+        "{}?exp={}&sig={}",
+        url,
+        expires,
+        sign(key, &metadata.id, &metadata.filename, expires)
+    ))
+}
+
+/// Helper fn to decide how the contents of an upload are presented, so that any scripts in them
+/// can not run on the site
+fn presentation(config: &Config, user_content: bool, metadata: &UploadMetadata) -> Presentation {
+    if !sniff::is_active(&metadata.media_type()) {
+        return Presentation::Inline;
+    }
+
+    match config.active_content {
+        ActiveContent::Attachment => Presentation::Attachment(metadata.filename.clone()),
+        // Nothing but uploads are trusted on the user content domain
+        ActiveContent::Domain if user_content => Presentation::Inline,
+        // Sandboxed when it could not be sent to the user content domain
+        ActiveContent::Domain | ActiveContent::Sandbox => Presentation::Sandboxed,
+    }
+}
+
+/// Endpoint to get a small preview of an uploaded image, which is made the first time it is requested.
+/// Previews do not use up views, so only signed in users can see those of uploads with limited views
+#[get("/u/<id>/thumb")]
+pub fn thumbnail(
+    auth: Option<Auth>,
+    config: State<Config>,
+    database: Database,
+    storage: State<Box<dyn Storage>>,
+    unlock: Unlock,
+    user_content: UserContentHost,
+    id: ID,
+) -> Result<BlobResponse, Status> {
+    let metadata = match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            return Err(Status::InternalServerError);
+        }
+        Ok(metadata) => metadata,
+    };
+
+    if !metadata.is_image() {
+        return Err(Status::NotFound);
+    } else if metadata.is_expired(Local::now().naive_local()) {
+        return Err(Status::Gone);
+    } else if auth.is_none()
+        && (metadata.visibility == Visibility::Private
+            || metadata.max_views.is_some()
+            || !unlock.allows(&id, metadata.password.as_deref()))
+    {
+        return Err(Status::Unauthorized);
+    }
+
+    let cached = database
+        .uploads()
+        .get_thumbnail(&metadata.sha256)
+        .map_err(|e| {
+            error!("Error fetching thumbnail: ID: {} Error: {}", id, e);
+
+            Status::InternalServerError
+        })?;
+    let thumbnail = match cached {
+        Some(blob) => {
+            let mut thumbnail = Vec::new();
+            storage
+                .open(&blob)
+                .and_then(|mut reader| reader.read_to_end(&mut thumbnail))
+                .map_err(|e| {
+                    error!(
+                        "Error fetching thumbnail data: ID: {} Blob: {} Error: {}",
+                        id, blob, e
+                    );
+
+                    Status::InternalServerError
+                })?;
+
+            Some(thumbnail)
+        }
+        None => make_thumbnail(&database, storage.as_ref(), &metadata)?,
+    };
+    let noindex = metadata.visibility != Visibility::Public;
+
+    match thumbnail {
+        Some(thumbnail) => Ok(BlobResponse {
+            content_type: thumbnail::content_type(&thumbnail),
+            size: thumbnail.len() as u64,
+            etag: format!("{}-thumb", metadata.sha256),
+            last_modified: metadata.timestamp,
+            reader: Box::new(io::Cursor::new(thumbnail)),
+            noindex,
+            ranges: true,
+            presentation: Presentation::Inline,
+        }),
+        // Send the image itself when it is small enough, or could not be made smaller
+        None => {
+            let reader = storage.open(&metadata.blob).map_err(|e| {
+                error!(
+                    "Error fetching file data: ID: {} Filename: {} Error: {}",
+                    id, metadata.filename, e
+                );
+
+                Status::InternalServerError
+            })?;
+
+            Ok(BlobResponse {
+                content_type: metadata.media_type(),
+                size: metadata.size,
+                presentation: presentation(&config, *user_content, &metadata),
+                etag: metadata.sha256,
+                last_modified: metadata.timestamp,
+                reader,
+                noindex,
+                ranges: true,
+            })
+        }
+    }
+}
+
+/// Helper fn to make the thumbnail of an uploaded image and store it for next time,
+/// returning nothing if the image should be used as its own thumbnail
+fn make_thumbnail(
+    database: &Database,
+    storage: &dyn Storage,
+    metadata: &UploadMetadata,
+) -> Result<Option<Vec<u8>>, Status> {
+    let mut reader = storage.open(&metadata.blob).map_err(|e| {
+        error!(
+            "Error fetching file data: ID: {} Filename: {} Error: {}",
+            metadata.id, metadata.filename, e
+        );
+
+        Status::InternalServerError
+    })?;
+
+    let thumbnail = match thumbnail::generate(&mut reader) {
+        Ok(Some(thumbnail)) => thumbnail,
+        Ok(None) => return Ok(None),
+        Err(ImageError::Unsupported(e)) => {
+            trace!(
+                "Can not make thumbnail: ID: {} Filename: {} Error: {}",
+                metadata.id,
+                metadata.filename,
+                e
+            );
+
+            return Ok(None);
+        }
+        Err(e) => {
+            warn!(
+                "Error making thumbnail: ID: {} Filename: {} Error: {}",
+                metadata.id, metadata.filename, e
+            );
+
+            return Ok(None);
+        }
+    };
+
+    // Failing to store the thumbnail only means that it will be made again next time
+    match storage.put(&mut thumbnail.as_slice()) {
+        Err(e) => error!(
+            "Error storing thumbnail: ID: {} Filename: {} Error: {}",
+            metadata.id, metadata.filename, e
+        ),
+        Ok(stored) => match database.uploads().save_thumbnail(&metadata.sha256, &stored) {
+            Err(e) => error!(
+                "Error saving thumbnail: ID: {} Filename: {} Error: {}",
+                metadata.id, metadata.filename, e
+            ),
+            Ok(unused) => {
+                for blob in unused {
+                    if let Err(e) = storage.delete(&blob) {
+                        warn!("Failed to remove duplicate blob {}: {}", blob, e);
+                    }
+                }
+            }
+        },
+    }
+
+    Ok(Some(thumbnail))
+}
+
+/// Endpoint to unlock a password protected upload with its password
+#[post("/u/<id>/<filename>", data = "<form>")]
+pub fn unlock<'r>(
+    config: State<'r, Config>,
+    database: Database,
+    mut unlocker: Unlocker,
+    id: ID,
+    filename: String,
+    form: Form<PasswordForm>,
+) -> Result<Locked<'r, Redirect>, Status> {
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => {
+            if metadata.filename != filename {
+                return Err(Status::NotFound);
+            }
+
+            let action = uri!(view: &id, filename).to_string();
+            match &metadata.password {
+                // Uploads without a password do not need to be unlocked
+                None => Ok(Locked::Unlocked(Redirect::to(action))),
+                Some(password) => {
+                    // Scope the cookie to the upload so that it unlocks both of its urls
+                    let path = format!("/u/{}", id);
+                    match unlocker.attempt(&id, password, &form.password, path) {
+                        Attempt::Unlocked => Ok(Locked::Unlocked(Redirect::to(action))),
+                        attempt => Ok(Locked::retry(config.inner(), "upload", action, attempt)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The page to set or remove the password of an upload
+#[get("/u/p/<id>", rank = 2)]
+pub fn protect<'r>(
+    auth: Option<Auth<'r>>,
+    config: State<'r, Config>,
+    database: Database,
+    id: ID,
+) -> Result<DOR<'r, ProtectTemplate<'r>>, Status> {
+    if auth.is_none() {
+        return Ok(DOR::login_and_return(uri!(protect: id)));
+    }
+
+    match database.uploads().get_upload_metatdata(&id) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Status::NotFound),
+        Err(e) => {
+            error!("Error fetching file metadata: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(metadata) => Ok(DOR::data(ProtectTemplate {
+            config: config.inner(),
+            resource_type: "upload",
+            name: metadata.filename,
+            protected: metadata.password.is_some(),
+        })),
+    }
+}
+
+/// Endpoint to set or remove the password of an upload, where an empty password removes it
+#[post("/u/p/<id>", data = "<form>", rank = 2)]
+pub fn protect_submit(
+    auth: Option<Auth>,
+    database: Database,
+    id: ID,
+    form: Form<PasswordForm>,
+) -> Result<Redirect, Status> {
+    if auth.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let password = hash_share_password(Some(&form.password))?;
+    match database.uploads().set_password(&id, password.as_deref()) {
+        Err(e) => {
+            error!("Error setting upload password: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(false) => Err(Status::NotFound),
+        Ok(true) => Ok(Redirect::to(uri!(all: _))),
+    }
+}
+
+/// The visibility submitted from the uploads page
+#[derive(FromForm, Debug)]
+pub struct VisibilityForm {
+    /// Who should be able to view the upload
+    visibility: Visibility,
+}
+
+/// Endpoint to change who can view an upload
+#[post("/u/v/<id>", data = "<form>", rank = 2)]
+pub fn set_visibility(
+    auth: Option<Auth>,
+    database: Database,
+    id: ID,
+    form: Form<VisibilityForm>,
+) -> Result<Status, Status> {
+    if auth.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    match database.uploads().set_visibility(&id, form.visibility) {
+        Err(e) => {
+            error!("Error setting upload visibility: ID: {} Error: {}", id, e);
+
+            Err(Status::InternalServerError)
+        }
+        Ok(false) => Err(Status::NotFound),
+        Ok(true) => Ok(Status::NoContent),
+    }
+}
+
 /// Endpoint to delete an uploaded assest by its ID
 #[get("/u/d/<id>", rank = 2)]
 pub fn delete_by_id(database: Database, id: ID) -> Result<Redirect, Status> {
@@ -174,6 +926,7 @@ pub fn delete_by_id(database: Database, id: ID) -> Result<Redirect, Status> {
 #[get("/u/d/<id>/<filename>")]
 pub fn delete<'r>(
     database: Database,
+    storage: State<Box<dyn Storage>>,
     config: State<'r, Config>,
     auth: Option<Auth<'r>>,
     id: ID,
@@ -189,20 +942,12 @@ pub fn delete<'r>(
             }
             Ok(metadata) => {
                 if metadata.filename == filename {
-                    match database.uploads().delete_upload(&id) {
-                        Err(e) => {
-                            error!(
-                                "Error deleting upload: ID: {} Filename: {} Error: {}",
-                                id, metadata.filename, e
-                            );
-
-                            Err(Status::InternalServerError)
-                        }
-                        Ok(()) => Ok(DOR::data(DeletedTemplate {
-                            config: config.inner(),
-                            resource_type: "upload",
-                        })),
-                    }
+                    remove(&database, storage.as_ref(), &metadata)?;
+
+                    Ok(DOR::data(DeletedTemplate {
+                        config: config.inner(),
+                        resource_type: "upload",
+                    }))
                 } else {
                     Err(Status::NotFound)
                 }
@@ -211,3 +956,34 @@ pub fn delete<'r>(
         None => Ok(DOR::login_and_return(uri!(delete: id, filename))),
     }
 }
+
+/// Delete an upload, along with its contents if no other upload shares them
+pub fn remove(
+    database: &Database,
+    storage: &dyn Storage,
+    metadata: &UploadMetadata,
+) -> Result<(), Status> {
+    match database.uploads().delete_upload(&metadata.id) {
+        Err(e) => {
+            error!(
+                "Error deleting upload: ID: {} Filename: {} Error: {}",
+                metadata.id, metadata.filename, e
+            );
+
+            Err(Status::InternalServerError)
+        }
+        Ok(blobs) => {
+            // Remove the contents if this was the last upload using them
+            for blob in blobs {
+                if let Err(e) = storage.delete(&blob) {
+                    error!(
+                        "Error deleting blob: ID: {} Blob: {} Error: {}",
+                        metadata.id, blob, e
+                    );
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
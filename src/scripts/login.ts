@@ -12,6 +12,9 @@ window.addEventListener("load", () => {
     const alert = form.querySelector<HTMLDivElement>("div.alert")!;
     alert.hidden = true;
 
+    /** The message shown in the alert box */
+    const message = alert.textContent ?? "";
+
     // Listen for the form's submission
     form.addEventListener("submit", async (e) => {
         // Prevent actual submission of the form
@@ -30,8 +33,14 @@ window.addEventListener("load", () => {
         if (response.status === 202) {
             // Send the user to the preferred redirect or to the home if there is none
             window.location.href = getParam("redirect") ?? "/";
+        } else if (response.status === 429) {
+            // Alert the user that they have been locked out
+            const minutes = Math.ceil(Number(response.headers.get("Retry-After") ?? 60) / 60);
+            alert.textContent = `Too many failed attempts, try again in ${minutes} minute${minutes === 1 ? "" : "s"}`;
+            alert.hidden = false;
         } else {
             // Alert the user of invalid username/password
+            alert.textContent = message;
             alert.hidden = false;
         }
     });
@@ -17,7 +17,7 @@ window.addEventListener("load", () => {
         let filename = file.name;
         let filecontents = await file.arrayBuffer();
 
-        await fetch(`${location.protocol}//${await (await fetch("/upload_url")).text()}/u?filename=${encodeURIComponent(filename)}`, {
+        let response = await fetch(`${location.protocol}//${await (await fetch("/upload_url")).text()}/u?filename=${encodeURIComponent(filename)}`, {
             body: filecontents,
             method: "POST",
             credentials: "include",
@@ -26,6 +26,11 @@ window.addEventListener("load", () => {
             }
         });
 
+        if (response.status === 413) {
+            alert(`${filename} is larger than the upload size limit or what is left of your quota`);
+            return;
+        }
+
         location.assign("/u");
     });
 });
\ No newline at end of file
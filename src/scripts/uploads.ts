@@ -8,6 +8,9 @@ window.addEventListener("load", () => {
         const { id, filename, domain, https } = upload.dataset;
         const delete_button = upload.querySelector<HTMLButtonElement>("button.delete")!;
         const copy_button = upload.querySelector<HTMLButtonElement>("button.copy")!;
+        const share_button = upload.querySelector<HTMLButtonElement>("button.share")!;
+        const password_button = upload.querySelector<HTMLButtonElement>("button.password")!;
+        const visibility_select = upload.querySelector<HTMLSelectElement>("select.visibility")!;
 
         upload.addEventListener("click", (e) => {
             e.stopImmediatePropagation();
@@ -22,6 +25,48 @@ window.addEventListener("load", () => {
                 location.href = `/u/d/${id}/${filename}`;
         });
 
+        password_button.addEventListener("click", (e) => {
+            e.stopImmediatePropagation();
+
+            location.href = `/u/p/${id}`;
+        });
+
+        share_button.addEventListener("click", async (e) => {
+            e.stopImmediatePropagation();
+
+            const expires = prompt(`How long should the link to ${filename} work for? (e.g. 30m, 12h, 7d)`, "1d");
+            if (expires === null)
+                return;
+
+            const response = await fetch(`/api/v1/uploads/${id}/sign?expires=${encodeURIComponent(expires)}`, {
+                method: "POST"
+            });
+
+            if (response.ok) {
+                const { url } = await response.json();
+                await navigator.clipboard.writeText(url);
+            } else {
+                alert(`Failed to create a link to ${filename}`);
+            }
+        });
+
+        visibility_select.addEventListener("click", (e) => {
+            e.stopImmediatePropagation();
+        });
+
+        visibility_select.addEventListener("change", async () => {
+            const response = await fetch(`/u/v/${id}`, {
+                method: "POST",
+                headers: {
+                    "Content-Type": "application/x-www-form-urlencoded"
+                },
+                body: `visibility=${encodeURIComponent(visibility_select.value)}`
+            });
+
+            if (!response.ok)
+                alert(`Failed to change the visibility of ${filename}`);
+        });
+
         copy_button.addEventListener("click", async (e) => {
             e.stopImmediatePropagation();
 
@@ -0,0 +1,179 @@
+//! Detection of the content type of uploads from the magic bytes at the start of their contents
+
+use rocket::http::ContentType;
+use std::path::Path;
+
+/// The amount of bytes from the start of an upload that are needed to detect its content type
+pub const SNIFF_LENGTH: usize = 512;
+
+/// A format that can be recognised by the bytes found at offsets from the start of its contents
+struct Signature {
+    /// The bytes that must be found at each offset
+    magic: &'static [(usize, &'static [u8])],
+    /// The top-level and sub type of the format
+    media_type: (&'static str, &'static str),
+    /// Whether other formats are built on top of this one, such as documents stored in zip files,
+    /// so that a known file extension should be trusted over it
+    container: bool,
+}
+
+/// Shorthand to make a signature that is matched by its magic bytes
+const fn signature(
+    magic: &'static [(usize, &'static [u8])],
+    top: &'static str,
+    sub: &'static str,
+    container: bool,
+) -> Signature {
+    Signature {
+        magic,
+        media_type: (top, sub),
+        container,
+    }
+}
+
+/// The formats that can be recognised, where the more specific ones come first
+const SIGNATURES: &[Signature] = &[
+    signature(&[(0, b"\x89PNG\r\n\x1a\n")], "image", "png", false),
+    signature(&[(0, b"\xff\xd8\xff")], "image", "jpeg", false),
+    signature(&[(0, b"GIF87a")], "image", "gif", false),
+    signature(&[(0, b"GIF89a")], "image", "gif", false),
+    signature(&[(0, b"RIFF"), (8, b"WEBP")], "image", "webp", false),
+    signature(&[(0, b"BM")], "image", "bmp", false),
+    signature(&[(0, b"\x00\x00\x01\x00")], "image", "x-icon", false),
+    signature(&[(0, b"II*\x00")], "image", "tiff", false),
+    signature(&[(0, b"MM\x00*")], "image", "tiff", false),
+    signature(&[(4, b"ftypavif")], "image", "avif", false),
+    signature(&[(4, b"ftypheic")], "image", "heic", false),
+    signature(&[(4, b"ftypqt  ")], "video", "quicktime", false),
+    signature(&[(4, b"ftypM4A ")], "audio", "mp4", false),
+    signature(&[(4, b"ftyp")], "video", "mp4", true),
+    signature(&[(0, b"\x1a\x45\xdf\xa3")], "video", "webm", true),
+    signature(&[(0, b"RIFF"), (8, b"AVI ")], "video", "x-msvideo", false),
+    signature(&[(0, b"RIFF"), (8, b"WAVE")], "audio", "wav", false),
+    signature(&[(0, b"OggS")], "audio", "ogg", true),
+    signature(&[(0, b"fLaC")], "audio", "flac", false),
+    signature(&[(0, b"ID3")], "audio", "mpeg", false),
+    signature(&[(0, b"\xff\xfb")], "audio", "mpeg", false),
+    signature(&[(0, b"%PDF-")], "application", "pdf", false),
+    signature(&[(0, b"PK\x03\x04")], "application", "zip", true),
+    signature(&[(0, b"\x1f\x8b")], "application", "gzip", false),
+    signature(
+        &[(0, b"7z\xbc\xaf\x27\x1c")],
+        "application",
+        "x-7z-compressed",
+        false,
+    ),
+    signature(&[(0, b"Rar!\x1a\x07")], "application", "vnd.rar", false),
+    signature(&[(0, b"\x00asm")], "application", "wasm", false),
+];
+
+/// Check if some contents look like text, which is valid UTF-8 without any control characters
+/// besides whitespace. The contents may be cut off part way through a character
+fn is_text(head: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(head) {
+        Ok(_) => head.len(),
+        // Only the last character was cut off
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => return false,
+    };
+    let text = std::str::from_utf8(&head[..valid]).unwrap_or_default();
+
+    !text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+}
+
+/// Check if a content type is for a format that is written as text
+#[must_use]
+pub fn is_textual(content_type: &ContentType) -> bool {
+    content_type.top() == "text"
+        || content_type.sub() == "json"
+        || content_type.sub() == "javascript"
+        || content_type.sub() == "xml"
+        || content_type.sub().as_str().ends_with("+xml")
+}
+
+/// Check if a content type is for a format that can run scripts when a browser shows or loads it,
+/// such as html, svg and javascript
+#[must_use]
+pub fn is_active(content_type: &ContentType) -> bool {
+    let sub = content_type.sub().as_str().to_ascii_lowercase();
+
+    sub == "html"
+        || sub == "xml"
+        || sub.ends_with("+xml")
+        || sub.ends_with("javascript")
+        || sub.ends_with("ecmascript")
+}
+
+/// Detect the content type of an upload from the start of its contents, using the extension of its filename
+/// to tell apart formats that share their magic bytes and formats that have none
+#[must_use]
+pub fn detect(head: &[u8], filename: &str) -> ContentType {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()));
+
+    let sniffed = SIGNATURES.iter().find(|signature| {
+        signature.magic.iter().all(|(offset, magic)| {
+            head.get(*offset..offset + magic.len())
+                .map_or(false, |bytes| bytes == *magic)
+        })
+    });
+
+    match (sniffed, extension) {
+        (Some(signature), Some(extension)) if signature.container => extension,
+        (Some(signature), _) => ContentType::new(signature.media_type.0, signature.media_type.1),
+        // Formats without magic bytes are trusted to be what their extension says when they look like it
+        (None, Some(extension)) if is_textual(&extension) == is_text(head) => extension,
+        (None, _) if is_text(head) => ContentType::Plain,
+        (None, _) => ContentType::Binary,
+    }
+}
+
+/// Test detecting content types from contents and filenames
+#[test]
+fn test_detect() {
+    let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+
+    assert_eq!(detect(png, "screenshot.png"), ContentType::PNG);
+    assert_eq!(detect(png, "notes.txt"), ContentType::PNG);
+    assert_eq!(detect(png, "unknown"), ContentType::PNG);
+    assert_eq!(
+        detect(b"\x1a\x45\xdf\xa3\x01", "song.weba"),
+        ContentType::WEBA
+    );
+    assert_eq!(
+        detect(b"\x1a\x45\xdf\xa3\x01", "unknown"),
+        ContentType::WEBM
+    );
+    assert_eq!(
+        detect(b"fn main() {}\n", "main.js"),
+        ContentType::JavaScript
+    );
+    assert_eq!(detect(b"fn main() {}\n", "unknown"), ContentType::Plain);
+    assert_eq!(
+        detect("caf\u{e9}".as_bytes(), "unknown"),
+        ContentType::Plain
+    );
+    assert_eq!(
+        detect(&"caf\u{e9}".as_bytes()[..4], "unknown"),
+        ContentType::Plain
+    );
+    assert_eq!(detect(b"\x00\x01\x02", "notes.txt"), ContentType::Binary);
+    assert_eq!(detect(b"\x00\x01\x02", "unknown"), ContentType::Binary);
+}
+
+/// Test which content types can run scripts
+#[test]
+fn test_is_active() {
+    assert!(is_active(&ContentType::HTML));
+    assert!(is_active(&ContentType::SVG));
+    assert!(is_active(&ContentType::XML));
+    assert!(is_active(&ContentType::new("application", "xhtml+xml")));
+    assert!(!is_active(&ContentType::Plain));
+    assert!(is_active(&ContentType::JavaScript));
+    assert!(is_active(&ContentType::new("text", "ecmascript")));
+    assert!(is_active(&ContentType::new("application", "x-javascript")));
+    assert!(!is_active(&ContentType::PNG));
+}
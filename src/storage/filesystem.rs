@@ -0,0 +1,120 @@
+//! Storage of blobs on the local filesystem
+
+use super::{BlobReader, Storage, StoredBlob};
+use crate::create_parent_directories;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// The size of the buffer used when streaming blobs to disk
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A storage backend that keeps blobs on the local filesystem, named by random hex keys
+#[derive(Debug)]
+pub struct FileStorage {
+    /// The directory that the blobs are stored in
+    root: PathBuf,
+}
+
+impl FileStorage {
+    /// Create a new filesystem storage backend rooted at the given directory
+    ///
+    /// # Errors
+    /// - If the root directory could not be created
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<FileStorage> {
+        let root = root.into();
+
+        // Create the root directory if it does not exist
+        if !root.exists() {
+            debug!("Creating storage directory {:?}", root);
+            fs::create_dir_all(&root)?;
+        }
+
+        Ok(FileStorage { root })
+    }
+
+    /// Get the path on disk that a blob with the given key is stored at
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        // Only allow hex keys to prevent escaping the storage directory
+        if key.len() < 3 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid blob key {:?}", key),
+            ));
+        }
+
+        // Shard the blobs into sub directories by the first two characters of their key
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+
+    /// Stream the data into a file, hashing it along the way
+    fn write_hashed(data: &mut dyn Read, path: &Path) -> io::Result<(String, u64)> {
+        let mut file = File::create(path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = [0_u8; BUFFER_SIZE];
+        let mut size = 0_u64;
+
+        loop {
+            let read = match data.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            hasher.input(&buffer[..read]);
+            file.write_all(&buffer[..read])?;
+            size += read as u64;
+        }
+
+        file.sync_all()?;
+
+        Ok((format!("{:x}", hasher.result()), size))
+    }
+}
+
+impl Storage for FileStorage {
+    fn put(&self, data: &mut dyn Read) -> io::Result<StoredBlob> {
+        // Every blob gets its own key, as an existing copy of the same contents could be removed
+        // at any moment. Duplicates are removed once the database knows which copy is kept
+        let key = Uuid::new_v4().to_simple().to_string();
+        let path = self.path(&key)?;
+
+        // Write to a temporary file first, so that partial blobs never show up in storage
+        let temp = self.root.join("tmp").join(&key);
+        create_parent_directories(&temp)?;
+
+        let (sha256, size) = match Self::write_hashed(data, &temp) {
+            Ok(written) => written,
+            Err(e) => {
+                // Clean up the partial blob
+                if let Err(e) = fs::remove_file(&temp) {
+                    warn!("Failed to remove partial blob {:?}: {}", temp, e);
+                }
+
+                return Err(e);
+            }
+        };
+
+        create_parent_directories(&path)?;
+        fs::rename(&temp, &path)?;
+
+        Ok(StoredBlob { key, sha256, size })
+    }
+
+    fn open(&self, key: &str) -> io::Result<Box<dyn BlobReader>> {
+        Ok(Box::new(File::open(self.path(key)?)?))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            // The blob is already gone
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
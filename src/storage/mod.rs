@@ -0,0 +1,71 @@
+//! Backends for storing the contents of uploads outside of the database
+
+use sha2::{Digest, Sha256};
+use std::io::{self, ErrorKind, Read, Seek};
+
+pub mod filesystem;
+
+/// The key that a stored blob can be retrieved with
+pub type BlobKey = String;
+
+/// The result of storing a blob in a storage backend
+#[derive(Debug)]
+pub struct StoredBlob {
+    /// The key that the blob can be retrieved with
+    pub key: BlobKey,
+    /// The size of the blob in bytes
+    pub size: u64,
+    /// The SHA-256 hash of the blob's contents, as lowercase hex
+    pub sha256: String,
+}
+
+/// Hash the contents of a stream with SHA-256, returning it as lowercase hex
+///
+/// # Errors
+/// - If the stream could not be read
+pub fn sha256(data: &mut dyn Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0_u8; 64 * 1024];
+
+    loop {
+        match data.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.input(&buffer[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(format!("{:x}", hasher.result()))
+}
+
+/// A reader over the contents of a stored blob
+pub trait BlobReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> BlobReader for T {}
+
+/// A backend that is able to store the contents of uploads
+pub trait Storage: Send + Sync {
+    /// Stream a blob into the backend, returning the key that it can be retrieved with and the hash
+    /// of its contents. Every blob is stored under a new key, even when its contents are already stored,
+    /// so that it can not be removed along with another copy. Nothing is stored if reading from the stream
+    /// fails part way through
+    ///
+    /// # Errors
+    /// - If the stream could not be read
+    /// - If the blob could not be written to the backend
+    fn put(&self, data: &mut dyn Read) -> io::Result<StoredBlob>;
+
+    /// Open the contents of a stored blob for reading
+    ///
+    /// # Errors
+    /// - If the blob does not exist
+    /// - If the blob could not be opened in the backend
+    fn open(&self, key: &str) -> io::Result<Box<dyn BlobReader>>;
+
+    /// Remove a stored blob from the backend
+    ///
+    /// # Errors
+    /// - If the blob could not be removed from the backend
+    fn delete(&self, key: &str) -> io::Result<()>;
+}
@@ -0,0 +1,161 @@
+//! Readers that wrap the streamed bodies of requests
+
+use derive_more::Display;
+use std::{
+    error::Error,
+    io::{self, ErrorKind, Read},
+};
+
+/// A reader that fails if its stream ends before the expected amount of bytes have been read
+#[derive(Debug)]
+pub struct ExpectedLength<R: Read> {
+    /// The stream being read from
+    inner: R,
+    /// The amount of bytes that the stream should contain, if known
+    expected: Option<u64>,
+    /// The amount of bytes that have been read so far
+    read: u64,
+}
+
+impl<R: Read> ExpectedLength<R> {
+    /// Wrap a stream that should contain the expected amount of bytes
+    pub fn new(inner: R, expected: Option<u64>) -> Self {
+        ExpectedLength {
+            inner,
+            expected,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ExpectedLength<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+
+        // The stream has ended, make sure that it was not cut short
+        if read == 0 && !buf.is_empty() {
+            if let Some(expected) = self.expected {
+                if self.read < expected {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!(
+                            "Stream ended after {} of {} expected bytes",
+                            self.read, expected
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// A reader that keeps a copy of the first bytes read from its stream
+#[derive(Debug)]
+pub struct HeadRecorder<R: Read> {
+    /// The stream being read from
+    inner: R,
+    /// The first bytes of the stream
+    head: Vec<u8>,
+    /// The amount of bytes to keep
+    length: usize,
+}
+
+impl<R: Read> HeadRecorder<R> {
+    /// Wrap a stream to keep up to the given amount of bytes from its start
+    pub fn new(inner: R, length: usize) -> Self {
+        HeadRecorder {
+            inner,
+            head: Vec::with_capacity(length),
+            length,
+        }
+    }
+
+    /// Get the bytes that have been kept from the start of the stream
+    #[must_use]
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+}
+
+impl<R: Read> Read for HeadRecorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        let wanted = self.length - self.head.len();
+        self.head.extend_from_slice(&buf[..read.min(wanted)]);
+
+        Ok(read)
+    }
+}
+
+/// The error returned by a `LimitedLength` reader once its stream goes over the limit
+#[derive(Debug, Display)]
+#[display(fmt = "Stream is longer than the limit of {} bytes", _0)]
+pub struct TooLarge(pub u64);
+
+impl Error for TooLarge {}
+
+impl TooLarge {
+    /// Check if an io error was caused by a stream going over its limit
+    #[must_use]
+    pub fn caused(error: &io::Error) -> bool {
+        error
+            .get_ref()
+            .map_or(false, |error| error.is::<TooLarge>())
+    }
+}
+
+/// A reader that fails once more than a limited amount of bytes have been read from its stream
+#[derive(Debug)]
+pub struct LimitedLength<R: Read> {
+    /// The stream being read from
+    inner: R,
+    /// The most bytes that can be read from the stream, if it is limited
+    limit: Option<u64>,
+    /// The amount of bytes that have been read so far
+    read: u64,
+}
+
+impl<R: Read> LimitedLength<R> {
+    /// Wrap a stream that should not go over the limit
+    pub fn new(inner: R, limit: Option<u64>) -> Self {
+        LimitedLength {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedLength<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+
+        match self.limit {
+            Some(limit) if self.read > limit => {
+                Err(io::Error::new(ErrorKind::Other, TooLarge(limit)))
+            }
+            _ => Ok(read),
+        }
+    }
+}
+
+/// Test that streams fail once they go over the limit
+#[test]
+fn test_limited_length() {
+    let read = |data: &[u8], limit| {
+        let mut contents = Vec::new();
+        LimitedLength::new(data, limit)
+            .read_to_end(&mut contents)
+            .map(|_| contents)
+    };
+
+    assert_eq!(read(b"sxfs", Some(4)).unwrap(), b"sxfs");
+    assert_eq!(read(b"sxfs", None).unwrap(), b"sxfs");
+    assert!(TooLarge::caused(&read(b"sxfs", Some(3)).unwrap_err()));
+    assert!(!TooLarge::caused(&io::Error::from(ErrorKind::Other)));
+}
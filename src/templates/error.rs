@@ -27,6 +27,16 @@ pub struct PageNotFoundTemplate {
     pub config: Config,
 }
 
+/// Template for a 410 gone error
+#[derive(Template)]
+#[template(path = "pages/errors/410.html")]
+pub struct GoneTemplate {
+    /// The resource that is no longer available
+    pub uri: String,
+    /// The site configuration
+    pub config: Config,
+}
+
 /// Template for a 500 internal error
 #[derive(Template)]
 #[template(path = "pages/errors/500.html")]
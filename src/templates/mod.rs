@@ -49,8 +49,12 @@ pub trait UpdatableTemplate: Template {
 #[derive(Template)]
 #[template(path = "config.toml", escape = "none")]
 pub struct ConfigTemplate<'a> {
-    /// A secure, custom upload token for this installation
+    /// A secure, custom key for encrypting session cookies
+    pub secret_key: &'a str,
+    /// A secure, custom key for signing time-limited urls
+    pub signing_key: &'a str,
+    /// A secure, custom upload token for the default account
     pub upload_token: &'a str,
-    /// The secure, custom password to use for the default account
-    pub admin_password: &'a str,
+    /// The hash of the secure, custom password to use for the default account
+    pub admin_password_hash: &'a str,
 }
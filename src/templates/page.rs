@@ -2,9 +2,14 @@
 
 use crate::{
     config::Config,
-    database::{LinkListing, UploadMetadata},
+    database::{
+        page::{ContentKind, LinkFilter, LinkSort, Order, UploadFilter, UploadSort},
+        AccessCount, LinkListing, Session, TopResource, UploadMetadata, Visibility,
+    },
+    viewer::Viewer,
 };
 use askama::Template;
+use chrono::NaiveDate;
 
 /// The template for the login page
 #[derive(Template)]
@@ -26,10 +31,26 @@ pub struct IndexTemplate<'a> {
     pub upload_count: u64,
     /// The total filesize of all the uploads
     pub space_count: u64,
+    /// The total views on the uploads combined
+    pub view_count: u64,
     /// The amount of links on the site
     pub link_count: u64,
     /// The total hits on the links combined
     pub total_hits: u32,
+    /// How much space each user's uploads take up
+    pub quotas: Box<[QuotaUsage<'a>]>,
+}
+
+/// How much space a user's uploads take up on the homepage
+pub struct QuotaUsage<'a> {
+    /// The username of the user
+    pub username: &'a str,
+    /// The combined size of the user's uploads
+    pub used: u64,
+    /// If the user has a quota
+    pub limited: bool,
+    /// The most space that the user's uploads can take up, when they have a quota
+    pub quota: u64,
 }
 
 /// The template for the uploads page
@@ -40,6 +61,44 @@ pub struct UploadsTemplate<'a> {
     pub config: &'a Config,
     /// The upload metadata to list with its index
     pub uploads: Box<[(usize, UploadMetadata)]>,
+    /// The filter applied to the uploads
+    pub filter: UploadFilter,
+    /// The field the uploads are sorted by
+    pub sort: UploadSort,
+    /// The direction the uploads are sorted in
+    pub order: Order,
+    /// The url of the previous page, if there is one
+    pub previous: Option<String>,
+    /// The url of the next page, if there is one
+    pub next: Option<String>,
+}
+
+/// The template for the preview page of an upload, which chat apps and social media embed
+#[derive(Template)]
+#[template(path = "pages/preview.html")]
+pub struct PreviewTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// The upload being previewed
+    pub upload: UploadMetadata,
+    /// The full url of the upload's contents
+    pub url: String,
+    /// The full url of the preview page
+    pub page_url: String,
+    /// How the upload is shown on the page, if it is shown at all
+    pub embed: Option<Viewer>,
+    /// The html of the highlighted code or rendered Markdown, for uploads shown as text
+    pub rendered: String,
+    /// The line numbers shown next to highlighted code
+    pub line_numbers: String,
+}
+
+impl<'a> PreviewTemplate<'a> {
+    /// Check if the upload is shown with the given viewer
+    #[must_use]
+    pub fn embeds(&self, viewer: Viewer) -> bool {
+        self.embed == Some(viewer)
+    }
 }
 
 /// The template for the links page
@@ -50,6 +109,16 @@ pub struct LinksTemplate<'a> {
     pub config: &'a Config,
     /// The upload metadata to list
     pub links: Box<[LinkListing]>,
+    /// The filter applied to the links
+    pub filter: LinkFilter,
+    /// The field the links are sorted by
+    pub sort: LinkSort,
+    /// The direction the links are sorted in
+    pub order: Order,
+    /// The url of the previous page, if there is one
+    pub previous: Option<String>,
+    /// The url of the next page, if there is one
+    pub next: Option<String>,
 }
 
 /// The template for the deleted page
@@ -61,3 +130,89 @@ pub struct DeletedTemplate<'a> {
     /// The type of resource that it was
     pub resource_type: &'a str,
 }
+
+/// The template for the prompt for the password of a protected upload or link
+#[derive(Template)]
+#[template(path = "pages/password.html")]
+pub struct PasswordTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// The type of resource that is protected
+    pub resource_type: &'a str,
+    /// The url to submit the password to
+    pub action: String,
+    /// The message to show when the previous attempt failed
+    pub message: Option<&'a str>,
+}
+
+/// The template for setting or removing the password of an upload or link
+#[derive(Template)]
+#[template(path = "pages/protect.html")]
+pub struct ProtectTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// The type of resource that is being protected
+    pub resource_type: &'a str,
+    /// The filename or uri of the resource
+    pub name: String,
+    /// Whether the resource already has a password
+    pub protected: bool,
+}
+
+/// The template for the sessions page
+#[derive(Template)]
+#[template(path = "pages/sessions.html")]
+pub struct SessionsTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// The active sessions of the logged in user
+    pub sessions: Box<[Session]>,
+    /// The id of the session that the page is being viewed from
+    pub current: String,
+}
+
+/// The template for the search page
+#[derive(Template)]
+#[template(path = "pages/search.html")]
+pub struct SearchTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// The text that was searched for
+    pub query: String,
+    /// The uploads with filenames matching the search
+    pub uploads: Box<[UploadMetadata]>,
+    /// The links with uris matching the search
+    pub links: Box<[LinkListing]>,
+}
+
+/// The amount of accesses on a single day of the stats page
+pub struct DailyAccesses {
+    /// The day the accesses happened on
+    pub day: NaiveDate,
+    /// The amount of accesses on the day
+    pub count: AccessCount,
+    /// The height of the day's bar, as a percentage of the busiest day
+    pub percent: AccessCount,
+}
+
+/// The template for the stats page
+#[derive(Template)]
+#[template(path = "pages/stats.html")]
+pub struct StatsTemplate<'a> {
+    /// The site configuration
+    pub config: &'a Config,
+    /// Whether accesses are being logged
+    pub enabled: bool,
+    /// The total accesses over all of the days shown
+    pub total: AccessCount,
+    /// The accesses on each of the days shown
+    pub days: Box<[DailyAccesses]>,
+    /// The most viewed uploads
+    pub top_uploads: Box<[TopResource]>,
+    /// The most followed links
+    pub top_links: Box<[TopResource]>,
+    /// The pages that linked to uploads and links the most
+    pub top_referrers: Box<[(String, AccessCount)]>,
+    /// The amount of different visitors
+    pub unique_visitors: AccessCount,
+}
@@ -1,7 +1,7 @@
 //! Templates for Share X Custom Uploaders
 
 use super::UpdatableTemplate;
-use crate::config::Config;
+use crate::config::{Config, ShareUrl};
 use askama::Template;
 
 /// Template for the uploader sxcu
@@ -18,19 +18,22 @@ pub struct UploaderTemplate<'a> {
     pub domain: &'a str,
     /// The protocol to use
     pub proto: &'a str,
+    /// Whether ShareX should be given the url of the preview page instead of the file
+    pub preview: bool,
 }
 
 impl<'a> UpdatableTemplate for UploaderTemplate<'a> {}
 impl<'a> UploaderTemplate<'a> {
-    /// Create a new uploader using the values from the config
+    /// Create a new uploader using the values from the config and a user's upload token
     #[must_use]
-    pub fn new(config: &'a Config) -> Self {
+    pub fn new(config: &'a Config, upload_token: &'a str) -> Self {
         UploaderTemplate {
             domain: &config.domain,
             name: &config.name,
             proto: if config.https { "https" } else { "http" },
             upload_domain: config.upload_domain.as_ref().unwrap_or(&config.domain),
-            upload_token: &config.upload_token,
+            upload_token,
+            preview: config.share_url == ShareUrl::Preview,
         }
     }
 }
@@ -51,14 +54,14 @@ pub struct ShortenerTemplate<'a> {
 
 impl<'a> UpdatableTemplate for ShortenerTemplate<'a> {}
 impl<'a> ShortenerTemplate<'a> {
-    /// Create a new shortener from the values in the config
+    /// Create a new shortener from the values in the config and a user's upload token
     #[must_use]
-    pub fn new(config: &'a Config) -> Self {
+    pub fn new(config: &'a Config, upload_token: &'a str) -> Self {
         ShortenerTemplate {
             domain: &config.domain,
             name: &config.name,
             proto: if config.https { "https" } else { "http" },
-            upload_token: &config.upload_token,
+            upload_token,
         }
     }
 }
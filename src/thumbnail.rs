@@ -0,0 +1,97 @@
+//! Generation of small previews of image uploads
+
+use image::{
+    error::{LimitError, LimitErrorKind},
+    io::Reader,
+    DynamicImage, ImageError, ImageFormat, ImageOutputFormat, ImageResult,
+};
+use rocket::http::ContentType;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+/// The largest width and height of a thumbnail
+pub const THUMBNAIL_SIZE: u32 = 400;
+
+/// The most pixels an image can have for a thumbnail to be made of it, to stop huge images from using up all the memory.
+/// Decoding an image this large takes about 100 MB
+const MAX_PIXELS: u64 = 25_000_000;
+
+/// The quality that thumbnails without transparency are encoded as jpegs with
+const JPEG_QUALITY: u8 = 80;
+
+/// Make a thumbnail of an image, encoded as a png if it has transparency or as a jpeg otherwise.
+/// Nothing is made if the image already fits within the size of a thumbnail, since it can be its own thumbnail
+///
+/// # Errors
+/// - If the image could not be read or decoded
+/// - If the image has too many pixels
+/// - If the thumbnail could not be encoded
+pub fn generate<R: Read + Seek>(image: &mut R) -> ImageResult<Option<Vec<u8>>> {
+    // Check the size first, which only needs the header of the image
+    let (width, height) = Reader::new(BufReader::new(&mut *image))
+        .with_guessed_format()?
+        .into_dimensions()?;
+
+    if width <= THUMBNAIL_SIZE && height <= THUMBNAIL_SIZE {
+        return Ok(None);
+    } else if u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    }
+
+    image.seek(SeekFrom::Start(0))?;
+    let image = Reader::new(BufReader::new(image))
+        .with_guessed_format()?
+        .decode()?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+
+    let mut thumbnail = Vec::new();
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_to(&mut thumbnail, ImageOutputFormat::Png)?;
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut thumbnail, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+    }
+
+    Ok(Some(thumbnail))
+}
+
+/// Get the content type of a thumbnail made by `generate`
+#[must_use]
+pub fn content_type(thumbnail: &[u8]) -> ContentType {
+    match image::guess_format(thumbnail) {
+        Ok(ImageFormat::Png) => ContentType::PNG,
+        _ => ContentType::JPEG,
+    }
+}
+
+/// Test that thumbnails are only made of images larger than a thumbnail, and fit within its size
+#[test]
+fn test_generate() {
+    use image::{GenericImageView, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    let encode = |image: DynamicImage| {
+        let mut encoded = Vec::new();
+        image.write_to(&mut encoded, ImageFormat::Png).unwrap();
+        Cursor::new(encoded)
+    };
+
+    let mut small = encode(DynamicImage::ImageRgb8(RgbImage::new(100, 50)));
+    assert!(generate(&mut small).unwrap().is_none());
+
+    let mut wide = encode(DynamicImage::ImageRgb8(RgbImage::new(1000, 500)));
+    let wide = generate(&mut wide).unwrap().unwrap();
+    assert_eq!(content_type(&wide), ContentType::JPEG);
+    let wide = image::load_from_memory(&wide).unwrap();
+    assert_eq!(wide.dimensions(), (400, 200));
+
+    let mut tall = encode(DynamicImage::ImageRgba8(RgbaImage::new(500, 1000)));
+    let tall = generate(&mut tall).unwrap().unwrap();
+    assert_eq!(content_type(&tall), ContentType::PNG);
+    let tall = image::load_from_memory(&tall).unwrap();
+    assert_eq!(tall.dimensions(), (200, 400));
+
+    assert!(generate(&mut Cursor::new(b"not an image".to_vec())).is_err());
+}
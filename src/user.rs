@@ -1,12 +1,107 @@
 //! Storage of user's info
 
+use argon2::{Config as HashConfig, Variant};
+use rand::RngCore;
 use rocket::request::FromForm;
+use subtle::ConstantTimeEq;
+
+/// Compare two secrets in constant time, so that the comparison does not leak how much of them matched
+#[must_use]
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
 
 /// A user that has access to the system
-#[derive(FromForm, Debug, PartialEq)]
+#[derive(Debug)]
 pub struct User {
     /// The user's login username
     pub username: String,
     /// The user's login password
+    pub password: Password,
+    /// The tokens that the user can upload with
+    pub tokens: Vec<String>,
+    /// The most bytes that the user's uploads can take up in total, if they are limited
+    pub quota: Option<u64>,
+}
+
+impl User {
+    /// Check if the given upload token belongs to this user, comparing against every token in constant time
+    pub fn has_token(&self, token: &str) -> bool {
+        self.tokens
+            .iter()
+            .fold(false, |found, t| constant_time_eq(t, token) | found)
+    }
+}
+
+/// A user's password as it is stored in the config
+#[derive(Debug)]
+pub enum Password {
+    /// An encoded argon2 hash of the password
+    Hashed(String),
+    /// **Deprecated:** The password in plain text, as written by older versions
+    Plaintext(String),
+}
+
+impl Password {
+    /// Interpret a password from the config, treating any encoded argon2 hashes as hashed
+    #[must_use]
+    pub fn from_config(value: String) -> Password {
+        if value.starts_with("$argon2") {
+            Password::Hashed(value)
+        } else {
+            Password::Plaintext(value)
+        }
+    }
+
+    /// Check if the given password matches this one
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            Password::Hashed(hash) => match argon2::verify_encoded(hash, password.as_bytes()) {
+                Ok(matches) => matches,
+                Err(e) => {
+                    error!("Error verifying password hash: {}", e);
+
+                    false
+                }
+            },
+            Password::Plaintext(plaintext) => constant_time_eq(plaintext, password),
+        }
+    }
+}
+
+/// Hash a password with a random salt, producing an encoded argon2 hash to store in the config
+///
+/// # Errors
+/// - If the password could not be hashed
+pub fn hash_password(password: &str) -> argon2::Result<String> {
+    let mut salt = [0_u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    argon2::hash_encoded(
+        password.as_bytes(),
+        &salt,
+        &HashConfig {
+            variant: Variant::Argon2id,
+            ..HashConfig::default()
+        },
+    )
+}
+
+/// The credentials submitted from the login form
+#[derive(FromForm, Debug)]
+pub struct Login {
+    /// The username that was entered
+    pub username: String,
+    /// The password that was entered
     pub password: String,
 }
+
+/// Test that hashed passwords can be verified
+#[test]
+fn test_password_hashing() {
+    let password = Password::from_config(hash_password("hunter2").expect("Failed to hash"));
+
+    assert!(matches!(password, Password::Hashed(_)));
+    assert!(password.verify("hunter2"));
+    assert!(!password.verify("hunter3"));
+}
@@ -0,0 +1,199 @@
+//! Viewers for showing uploads on their preview pages, which highlight code and render Markdown
+
+use crate::database::{page::ContentKind, UploadMetadata};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use std::path::Path;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// The largest text upload in bytes that is highlighted or rendered, as bigger ones make slow and huge pages
+pub const MAX_TEXT_SIZE: u64 = 1024 * 1024;
+
+/// The theme that highlighted code is coloured with
+const THEME: &str = "base16-ocean.dark";
+
+/// The prefix of the classes of highlighted code, so that they do not clash with the page's own classes
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The ways that an upload can be shown on its preview page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Viewer {
+    /// Shown as an image
+    Image,
+    /// Played as a video
+    Video,
+    /// Played as audio
+    Audio,
+    /// Shown in the browser's PDF viewer
+    Pdf,
+    /// Shown as highlighted code with line numbers
+    Code,
+    /// Rendered from Markdown
+    Markdown,
+}
+
+impl Viewer {
+    /// Choose how an upload is shown from its content type and the extension of its filename,
+    /// if it can be shown at all
+    #[must_use]
+    pub fn for_upload(metadata: &UploadMetadata) -> Option<Viewer> {
+        Some(match metadata.media_kind()? {
+            ContentKind::Image => Viewer::Image,
+            ContentKind::Video => Viewer::Video,
+            ContentKind::Audio => Viewer::Audio,
+            ContentKind::Text if metadata.media_type().sub() == "pdf" => Viewer::Pdf,
+            ContentKind::Text if is_markdown(&metadata.filename) => Viewer::Markdown,
+            ContentKind::Text => Viewer::Code,
+        })
+    }
+
+    /// Check if the viewer shows the contents of the upload as text, which has to be read to render the page
+    #[must_use]
+    pub fn is_text(self) -> bool {
+        self == Viewer::Code || self == Viewer::Markdown
+    }
+}
+
+/// Helper fn to check if a file is Markdown from its extension
+fn is_markdown(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .map_or(false, |ext| ext == "md" || ext == "markdown")
+}
+
+/// Highlighter for code, which loads the syntaxes and theme once to be shared between requests
+pub struct Highlighter {
+    /// The syntaxes of the languages that can be highlighted
+    syntaxes: SyntaxSet,
+    /// The theme used for the stylesheet
+    theme: Theme,
+}
+
+impl Highlighter {
+    /// Load the bundled syntaxes and theme
+    #[must_use]
+    pub fn new() -> Self {
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: ThemeSet::load_defaults().themes[THEME].clone(),
+        }
+    }
+
+    /// Highlight code as html, choosing the language from the extension of the filename
+    /// or from the first line when the extension is not known
+    #[must_use]
+    pub fn highlight(&self, code: &str, filename: &str) -> String {
+        let syntax = Path::new(filename)
+            .extension()
+            .and_then(|ext| {
+                self.syntaxes
+                    .find_syntax_by_extension(&ext.to_string_lossy())
+            })
+            .or_else(|| self.syntaxes.find_syntax_by_first_line(code))
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line);
+        }
+
+        generator.finalize()
+    }
+
+    /// Get the stylesheet that colours highlighted code
+    #[must_use]
+    pub fn stylesheet(&self) -> String {
+        css_for_theme_with_class_style(&self.theme, CLASS_STYLE)
+    }
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Highlighter::new()
+    }
+}
+
+/// Get the line numbers shown next to highlighted code, one on each line
+#[must_use]
+pub fn line_numbers(code: &str) -> String {
+    (1..=code.lines().count().max(1))
+        .map(|number| number.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The schemes that links and images in Markdown can use, as others such as `javascript:` could run scripts
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Helper fn to check if a url in Markdown is safe to follow, which is when it is relative or has a safe scheme
+fn is_safe_url(url: &str) -> bool {
+    // The scheme is whatever comes before the first colon, unless the path, query or fragment starts first
+    match url.find(|c| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(end) if url[end..].starts_with(':') => SAFE_SCHEMES
+            .iter()
+            .any(|scheme| url[..end].eq_ignore_ascii_case(scheme)),
+        _ => true,
+    }
+}
+
+/// Helper fn to replace the url of a link or image in Markdown with one to nowhere if it is not safe
+fn safe_url(url: CowStr) -> CowStr {
+    if is_safe_url(&url) {
+        url
+    } else {
+        CowStr::Borrowed("#")
+    }
+}
+
+/// Render Markdown as html, where any html written in it is shown as text instead of being kept
+/// and links or images with unsafe urls lead nowhere
+#[must_use]
+pub fn render_markdown(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::all()).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        event => event,
+    });
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, parser);
+
+    rendered
+}
+
+/// Test that Markdown is rendered without keeping any html or unsafe urls written in it
+#[test]
+fn test_render_markdown() {
+    assert_eq!(
+        render_markdown("# Title\n\nSome *text*"),
+        "<h1>Title</h1>\n<p>Some <em>text</em></p>\n"
+    );
+    assert!(!render_markdown("<script>alert(1)</script>").contains("<script>"));
+    assert!(!render_markdown("Inline <b onclick=\"x\">html</b>").contains("<b"));
+    assert_eq!(
+        render_markdown("[a](https://example.com) [b](notes.md) [c](mailto:a@example.com)"),
+        "<p><a href=\"https://example.com\">a</a> <a href=\"notes.md\">b</a> \
+         <a href=\"mailto:a@example.com\">c</a></p>\n"
+    );
+    assert!(!render_markdown("[a](javascript:alert(1))").contains("javascript"));
+    assert!(!render_markdown("[a](JavaScript&colon;alert(1))").contains("alert"));
+    assert!(!render_markdown("![a](data:image/svg+xml,x)").contains("data:"));
+}
+
+/// Test numbering the lines of highlighted code
+#[test]
+fn test_line_numbers() {
+    assert_eq!(line_numbers("a\nb\n"), "1\n2");
+    assert_eq!(line_numbers("a\nb"), "1\n2");
+    assert_eq!(line_numbers(""), "1");
+}